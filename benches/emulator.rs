@@ -0,0 +1,36 @@
+// Emulation speed on the ROMs of benches/roms, each one stressing a part of the core :
+// alu the Cpu, memory the Ram and sprites the Display. Run with cargo bench.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use tlmc::emulator::Emulator;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::{load_rom, run_frames, INSTRUCTIONS_PER_FRAME};
+
+const ROMS: [&str; 3] = ["alu", "memory", "sprites"];
+// One second of emulation per iteration.
+const FRAMES: u32 = 60;
+
+fn emulation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("emulation");
+    group.throughput(Throughput::Elements((FRAMES * INSTRUCTIONS_PER_FRAME) as u64));
+
+    for name in ROMS {
+        let rom = load_rom(&format!("benches/roms/{}.asm", name));
+
+        for (interpreter, instruction_cache) in [("cached", true), ("plain", false)] {
+            let mut emulator = Emulator::new();
+            emulator.set_seed(0);
+            emulator.load_rom_from_bytes(&rom).unwrap();
+            emulator.set_instruction_cache(instruction_cache);
+
+            group.bench_function(BenchmarkId::new(name, interpreter), |b| b.iter(|| run_frames(&mut emulator, FRAMES, &[])));
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, emulation);
+criterion_main!(benches);
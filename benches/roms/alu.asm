@@ -0,0 +1,22 @@
+; Register arithmetic for the Cpu benchmark : the 8XYN instructions, the skips and
+; the jumps, in an endless loop.
+    LD V0, 0x00
+    LD V1, 0x01
+loop:
+    ADD V0, V1
+    ADD V1, 0x03
+    LD V2, V0
+    OR V2, V1
+    AND V3, V2
+    XOR V3, V0
+    SUB V3, V1
+    SUBN V4, V3
+    SHR V4
+    SHL V5, V4
+    ADD V5, V0
+    SE V0, 0x80
+    SNE V1, V0
+    LD V6, 0x00
+    SE V2, V3
+    ADD V6, 0x01
+    JP loop
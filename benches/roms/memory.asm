@@ -0,0 +1,22 @@
+; Memory traffic for the Ram benchmark : BCD conversions, register stores and loads,
+; and an instruction patched on every pass (LD V3 gets the value of V0).
+    LD V0, 0x00
+loop:
+    LD I, buffer
+    LD B, V0
+    LD V2, [I]
+    LD [I], VF
+    LD VF, [I]
+    ADD I, V2
+    LD [I], V3
+    LD I, patch
+    LD [I], V0
+    DB 0x63
+patch:
+    DB 0x00
+    ADD V0, 0x01
+    JP loop
+buffer:
+    DB 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+    DB 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+    DB 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
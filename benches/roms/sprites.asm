@@ -0,0 +1,22 @@
+; Sprite drawing for the Display benchmark : large sprites wrapping around the screen
+; edges, font glyphs and screen clears.
+    LD V0, 0x00
+    LD V1, 0x00
+    LD V2, 0x00
+loop:
+    LD I, block
+    DRW V0, V1, 15
+    ADD V0, 0x07
+    ADD V1, 0x05
+    DRW V1, V0, 15
+    LD F, V2
+    DRW V0, V2, 5
+    ADD V2, 0x01
+    SNE V2, 0x40
+    CLS
+    SNE V2, 0x40
+    LD V2, 0x00
+    JP loop
+block:
+    DB 0xFF, 0x81, 0xBD, 0xA5, 0xA5, 0xBD, 0x81, 0xFF
+    DB 0x18, 0x3C, 0x7E, 0xFF, 0x7E, 0x3C, 0x18
@@ -0,0 +1,97 @@
+#![no_main]
+
+// Arbitrary ROMs, settings and key presses : the emulator must never panic. Loading
+// returns Ok or the RomError of the ROM size, and each instruction either runs or
+// returns the CpuError of the opcode at the PC, leaving the state untouched, with SP
+// within the stack. PC and I are plain u16 registers that may point past the end of
+// memory (BNNN, FX1E, a skip on the last instruction), the memory map deciding what
+// the accesses there do : unless the addresses wrap, they read 0x0000, which is no
+// instruction, so an instruction that runs was within memory.
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use tlmc::cpu::CpuError;
+use tlmc::emulator::{Emulator, RomError};
+use tlmc::instruction::InstructionSet;
+use tlmc::quirks::Quirks;
+use tlmc::ram::{MemoryMap, OutOfRange, CLASSIC_MEMORY_SIZE, XO_MEMORY_SIZE};
+
+const ROM_START: usize = 0x200;
+const MAX_FRAMES: usize = 64;
+const INSTRUCTIONS_PER_FRAME: usize = 32;
+const KEYS_COUNT: u8 = 16;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    rom: Vec<u8>,
+    // The keypad during each frame, one bit per key.
+    keys: Vec<u16>,
+    quirks: [bool; 7],
+    schip: bool,
+    xo_memory: bool,
+    out_of_range: u8,
+    protect_interpreter: bool,
+    seed: u64
+}
+
+fuzz_target!(|input: Input| {
+    let mut emulator = Emulator::new();
+    emulator.set_seed(input.seed);
+    emulator.set_quirks(Quirks {
+        vf_reset: input.quirks[0],
+        shift: input.quirks[1],
+        load_store: input.quirks[2],
+        jump: input.quirks[3],
+        clip: input.quirks[4],
+        display_wait: input.quirks[5],
+        vf_order: input.quirks[6]
+    });
+    emulator.set_instruction_set(if input.schip {InstructionSet::Schip} else {InstructionSet::Chip8});
+    let out_of_range = match input.out_of_range % 3 {
+        0 => OutOfRange::Ignore,
+        1 => OutOfRange::Wrap,
+        _ => OutOfRange::Fault
+    };
+    emulator.set_memory_map(MemoryMap {
+        size: if input.xo_memory {XO_MEMORY_SIZE} else {CLASSIC_MEMORY_SIZE},
+        out_of_range,
+        protect_interpreter: input.protect_interpreter
+    });
+
+    match emulator.load_rom_from_bytes(&input.rom) {
+        Ok(()) => assert!(!input.rom.is_empty() && ROM_START + input.rom.len() <= XO_MEMORY_SIZE),
+        Err(RomError::Empty) => return assert!(input.rom.is_empty()),
+        Err(RomError::TooLarge(size)) => return assert!(size == input.rom.len() && ROM_START + size > XO_MEMORY_SIZE),
+        Err(why) => panic!("Unexpected ROM error -> {}", why)
+    }
+
+    for frame in 0..MAX_FRAMES {
+        let keys = input.keys.get(frame).copied().unwrap_or(0);
+        for key in 0..KEYS_COUNT {
+            emulator.key(key, keys & (1 << key) != 0);
+        }
+
+        for _ in 0..INSTRUCTIONS_PER_FRAME {
+            let pc = emulator.get_pc();
+            let state = (pc, emulator.get_sp(), emulator.get_index_reg());
+            let opcode = (emulator.read_memory(pc) as u16) << 8 | emulator.read_memory(pc.wrapping_add(1)) as u16;
+
+            if let Err(why) = emulator.run_instruction() {
+                let sp = emulator.get_sp() as usize;
+                match why {
+                    CpuError::UnknownOpcode(unknown) => assert_eq!(unknown, opcode),
+                    CpuError::StackOverflow => assert!(opcode & 0xF000 == 0x2000 && sp == emulator.get_stack().len()),
+                    CpuError::StackUnderflow => assert!(opcode == 0x00EE && sp == 0)
+                }
+                assert_eq!((emulator.get_pc(), emulator.get_sp(), emulator.get_index_reg()), state);
+                return;
+            }
+            assert!(emulator.get_sp() as usize <= emulator.get_stack().len());
+            assert!(out_of_range == OutOfRange::Wrap || (pc as usize) < emulator.memory_size());
+
+            emulator.take_watch_hit();
+        }
+
+        emulator.update_cpu_timers();
+    }
+});
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+const ROM_START: u16 = 0x200;
+
+// Assembles the mnemonics printed by the disassembler (Cowgod's syntax), with labels
+// ("loop:"), DB/DW data directives and ";" comments.
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} : {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = ROM_START as usize;
+
+    // first pass : label addresses.
+    for (i, line) in source.lines().enumerate() {
+        let mut code = line.split(';').next().unwrap().trim();
+
+        while let Some((label, rest)) = code.split_once(':') {
+            let label = label.trim();
+            if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            if labels.insert(label.to_string(), addr as u16).is_some() {
+                return Err(AsmError { line: i + 1, message: format!("Duplicate label {}", label) });
+            }
+            code = rest.trim();
+        }
+
+        if code.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        let operands: Vec<&str> = operands.split(',').map(|operand| operand.trim()).filter(|operand| !operand.is_empty()).collect();
+        let mnemonic = mnemonic.to_ascii_uppercase();
+
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => 2 * operands.len(),
+            _ => 2
+        };
+
+        statements.push(Statement { line: i + 1, mnemonic, operands });
+    }
+
+    if addr > 0x1000 {
+        return Err(AsmError { line: source.lines().count(), message: format!("The program is {} bytes long, it does not fit into memory", addr - ROM_START as usize) });
+    }
+
+    // second pass : encoding.
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let error = |message: String| AsmError { line: statement.line, message };
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in &statement.operands {
+                    rom.push(value(operand, &labels, 0xFF).map_err(error)? as u8);
+                }
+            },
+            "DW" => {
+                for operand in &statement.operands {
+                    rom.extend_from_slice(&value(operand, &labels, 0xFFFF).map_err(error)?.to_be_bytes());
+                }
+            },
+            _ => {
+                let opcode = encode(&statement.mnemonic, &statement.operands, &labels).map_err(error)?;
+                rom.extend_from_slice(&opcode.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(rom)
+}
+
+fn encode(mnemonic: &str, operands: &[&str], labels: &HashMap<String, u16>) -> Result<u16, String> {
+    let upper: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+    let upper: Vec<&str> = upper.iter().map(|operand| operand.as_str()).collect();
+
+    let reg = |i: usize| register(operands[i]);
+    let vx = |i: usize| expect_register(operands[i]);
+    let nnn = |i: usize| value(operands[i], labels, 0xFFF);
+    let nn = |i: usize| value(operands[i], labels, 0xFF).map(|value| value as u8);
+
+    let instruction = match (mnemonic, upper.as_slice()) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SYS", [_]) => Instruction::Sys(nnn(0)?),
+        ("JP", ["V0", _]) => Instruction::JpV0(nnn(1)?),
+        ("JP", [_]) => Instruction::Jp(nnn(0)?),
+        ("CALL", [_]) => Instruction::Call(nnn(0)?),
+        ("SE", [_, _]) => match (reg(0), reg(1)) {
+            (Some(x), Some(y)) => Instruction::SeReg(x, y),
+            (Some(x), None) => Instruction::SeImm(x, nn(1)?),
+            _ => return Err(invalid_operands(mnemonic, operands))
+        },
+        ("SNE", [_, _]) => match (reg(0), reg(1)) {
+            (Some(x), Some(y)) => Instruction::SneReg(x, y),
+            (Some(x), None) => Instruction::SneImm(x, nn(1)?),
+            _ => return Err(invalid_operands(mnemonic, operands))
+        },
+        ("LD", ["I", _]) => Instruction::LdI(nnn(1)?),
+        ("LD", ["DT", _]) => Instruction::LdDtVx(vx(1)?),
+        ("LD", ["ST", _]) => Instruction::LdStVx(vx(1)?),
+        ("LD", ["F", _]) => Instruction::LdF(vx(1)?),
+        ("LD", ["HF", _]) => Instruction::LdHf(vx(1)?),
+        ("LD", ["B", _]) => Instruction::LdB(vx(1)?),
+        ("LD", ["[I]", _]) => Instruction::LdIVx(vx(1)?),
+        ("LD", [_, "DT"]) => Instruction::LdVxDt(vx(0)?),
+        ("LD", [_, "K"]) => Instruction::LdVxK(vx(0)?),
+        ("LD", [_, "[I]"]) => Instruction::LdVxI(vx(0)?),
+        ("LD", [_, _]) => match (reg(0), reg(1)) {
+            (Some(x), Some(y)) => Instruction::LdReg(x, y),
+            (Some(x), None) => Instruction::LdImm(x, nn(1)?),
+            _ => return Err(invalid_operands(mnemonic, operands))
+        },
+        ("ADD", ["I", _]) => Instruction::AddI(vx(1)?),
+        ("ADD", [_, _]) => match (reg(0), reg(1)) {
+            (Some(x), Some(y)) => Instruction::AddReg(x, y),
+            (Some(x), None) => Instruction::AddImm(x, nn(1)?),
+            _ => return Err(invalid_operands(mnemonic, operands))
+        },
+        ("OR", [_, _]) => Instruction::Or(vx(0)?, vx(1)?),
+        ("AND", [_, _]) => Instruction::And(vx(0)?, vx(1)?),
+        ("XOR", [_, _]) => Instruction::Xor(vx(0)?, vx(1)?),
+        ("SUB", [_, _]) => Instruction::Sub(vx(0)?, vx(1)?),
+        ("SUBN", [_, _]) => Instruction::Subn(vx(0)?, vx(1)?),
+        ("SHR", [_]) => Instruction::Shr(vx(0)?, 0x0),
+        ("SHR", [_, _]) => Instruction::Shr(vx(0)?, vx(1)?),
+        ("SHL", [_]) => Instruction::Shl(vx(0)?, 0x0),
+        ("SHL", [_, _]) => Instruction::Shl(vx(0)?, vx(1)?),
+        ("RND", [_, _]) => Instruction::Rnd(vx(0)?, nn(1)?),
+        ("DRW", [_, _, _]) => Instruction::Drw(vx(0)?, vx(1)?, value(operands[2], labels, 0xF)? as u8),
+        ("SKP", [_]) => Instruction::Skp(vx(0)?),
+        ("SKNP", [_]) => Instruction::Sknp(vx(0)?),
+        ("CLS", _) | ("RET", _) | ("SYS", _) | ("JP", _) | ("CALL", _) | ("SE", _) | ("SNE", _) | ("LD", _) |
+        ("ADD", _) | ("OR", _) | ("AND", _) | ("XOR", _) | ("SUB", _) | ("SUBN", _) | ("SHR", _) | ("SHL", _) |
+        ("RND", _) | ("DRW", _) | ("SKP", _) | ("SKNP", _) => return Err(invalid_operands(mnemonic, operands)),
+        _ => return Err(format!("Unknown instruction {}", mnemonic))
+    };
+
+    Ok(instruction.encode())
+}
+
+fn invalid_operands(mnemonic: &str, operands: &[&str]) -> String {
+    format!("Invalid operands for {} -> {}", mnemonic, operands.join(", "))
+}
+
+fn register(operand: &str) -> Option<u8> {
+    let digit = operand.strip_prefix('V').or_else(|| operand.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn expect_register(operand: &str) -> Result<u8, String> {
+    register(operand).ok_or_else(|| format!("Expected a register, found {}", operand))
+}
+
+// A number (0x1F, 0b0101, 31) or a label, at most max.
+fn value(operand: &str, labels: &HashMap<String, u16>, max: u16) -> Result<u16, String> {
+    let parsed = if let Some(hex) = operand.strip_prefix("0x").or_else(|| operand.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = operand.strip_prefix("0b").or_else(|| operand.strip_prefix("0B")) {
+        u32::from_str_radix(binary, 2).ok()
+    } else if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        operand.parse().ok()
+    } else {
+        labels.get(operand).map(|addr| *addr as u32)
+    };
+
+    match parsed {
+        Some(parsed) if parsed <= max as u32 => Ok(parsed as u16),
+        Some(_) => Err(format!("{} does not fit into 0x{:X}", operand, max)),
+        None if operand.starts_with(|c: char| c.is_ascii_digit()) => Err(format!("Invalid number {}", operand)),
+        None => Err(format!("Unknown label {}", operand))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::disassembler::disassemble;
+    use crate::instruction::{decode, InstructionSet};
+
+    #[test]
+    fn disassembled_opcodes_assemble_back() {
+        for opcode in 0..=0xFFFF {
+            if decode(opcode, InstructionSet::Schip).is_ok() {
+                let mnemonic = disassemble(opcode);
+                let rom = assemble(&mnemonic).unwrap_or_else(|why| panic!("{} : {}", mnemonic, why));
+                assert_eq!(rom, opcode.to_be_bytes(), "{}", mnemonic);
+            }
+        }
+    }
+}
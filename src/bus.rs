@@ -0,0 +1,21 @@
+use crate::instruction::{decode, DecodeError, Instruction, InstructionSet};
+
+// Memory as seen by the CPU. Every access of a program goes through a bus, so the
+// memory map (size, out of range accesses, protected areas) and the debugging hooks
+// (watchpoints, access log) live behind it.
+pub trait Bus {
+    // Instruction fetches, not seen by watchpoints.
+    fn fetch(&self, addr: u16) -> u8;
+
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    // Fetches and decodes the instruction at the address. Buses may return a cached
+    // instruction, as long as it matches the bytes in memory.
+    fn fetch_instruction(&mut self, addr: u16, instruction_set: InstructionSet) -> Result<Instruction, DecodeError> {
+        let opcode = (self.fetch(addr) as u16) << 8 | self.fetch(addr.wrapping_add(1)) as u16;
+
+        decode(opcode, instruction_set)
+    }
+}
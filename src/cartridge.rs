@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+use crate::gif;
+use crate::octo_options::OctoOptions;
+
+// An Octo cartridge is a GIF image whose pixels carry a payload, 2 bits per pixel in the
+// low bits of the palette index, most significant bits first, across all the frames.
+// The payload is a 32-bit big-endian length followed by a JSON object holding the Octo
+// source of the program and its options.
+#[derive(Deserialize)]
+pub struct Cartridge {
+    pub program: String,
+    #[serde(default)]
+    pub options: OctoOptions
+}
+
+pub fn is_cartridge(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+pub fn decode(data: &[u8]) -> Result<Cartridge, String> {
+    let frames = gif::decode_frames(data)?;
+
+    let indices: Vec<u8> = frames.iter().flat_map(|frame| frame.pixels.iter().map(|index| index & 0x3)).collect();
+    let payload: Vec<u8> = indices.chunks_exact(4).map(|bits| bits[0] << 6 | bits[1] << 4 | bits[2] << 2 | bits[3]).collect();
+
+    if payload.len() < 4 {
+        return Err("the image does not hold a cartridge".to_string());
+    }
+
+    let size = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    let json = payload.get(4..4 + size).ok_or("the cartridge payload is truncated")?;
+
+    serde_json::from_slice(json).map_err(|why| format!("invalid cartridge payload ({})", why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, is_cartridge};
+
+    // Two 32x15 frames carrying a small program and its options, see tests/fixtures.
+    static CARTRIDGE: &[u8] = include_bytes!("../tests/fixtures/cartridge.gif");
+
+    #[test]
+    fn payload_is_read_across_the_frames() {
+        assert!(is_cartridge(CARTRIDGE));
+
+        let cartridge = decode(CARTRIDGE).unwrap();
+        assert!(cartridge.program.contains("if v0 < v1 then v2 := 1"));
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert_eq!(cartridge.options.fill_color.as_deref(), Some("#FFCC00"));
+        assert_eq!(cartridge.options.load_store_quirks, Some(true));
+        assert_eq!(cartridge.options.vf_order_quirks, None);
+    }
+
+    #[test]
+    fn images_without_a_payload_are_errors() {
+        // the header and the first frame only, the payload is cut. The header and its
+        // palette take 25 bytes, the frames the same size and the trailer 1.
+        let frame_size = (CARTRIDGE.len() - 25 - 1) / 2;
+        let truncated = [&CARTRIDGE[..25 + frame_size], b"\x3B"].concat();
+        assert!(matches!(decode(&truncated), Err(why) if why.contains("truncated")));
+
+        assert!(!is_cartridge(b"\x00\xE0\x12\x00"));
+    }
+}
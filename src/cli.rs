@@ -0,0 +1,386 @@
+use std::path::PathBuf;
+
+use tlmc::config::{self, parse_color, Settings};
+use tlmc::font::{FontStyle, MAX_FONT_ADDRESS};
+use crate::keymap::Keymap;
+use tlmc::netplay::{self, MAX_INPUT_DELAY};
+use tlmc::quirks::Quirks;
+use tlmc::ram::{OutOfRange, WatchKind};
+use tlmc::tracer::{TraceFormat, TraceFilter};
+
+const DEFAULT_TEST_FRAMES: u32 = 600;
+const DEFAULT_BENCH_FRAMES: u32 = 6000;
+const MAX_SCALE: u32 = 40;
+
+#[derive(Default)]
+pub struct Options {
+    pub gdb_port: Option<u16>,
+    pub watchpoints: Vec<(u16, u16, WatchKind)>,
+    pub access_log_path: Option<String>,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub profile: bool,
+    pub profile_path: Option<String>,
+    pub coverage_path: Option<String>,
+    pub coverage_image_path: Option<String>,
+    pub rom_dir: Option<PathBuf>,
+    pub watch: bool,
+    pub keep_state: bool,
+    // Octo options file, instead of the one next to the ROM.
+    pub octo_options_path: Option<String>,
+    pub netplay: Option<NetplayRole>,
+    // Keys of the local player during netplay, bit N for key N.
+    pub own_keys: Option<u16>,
+    pub input_delay: Option<u32>,
+    // Overrides the configuration file and the ROM database.
+    pub settings: Settings
+}
+
+pub enum NetplayRole {
+    // Waits for the other player on the port.
+    Host(u16),
+    // Address of the host.
+    Join(String)
+}
+
+// How long a headless run lasts.
+#[derive(Clone, Copy)]
+pub enum RunLength {
+    Frames(u32),
+    Instructions(u64)
+}
+
+pub enum Command {
+    Run { rom_path: Option<String>, options: Options },
+    Disasm { rom_path: String, coverage_path: Option<String> },
+    Asm { source_path: String, output_path: String },
+    Info { rom_path: String, options: Options },
+    Test { rom_path: String, frames: u32, expect_path: Option<String>, options: Options },
+    Bench { rom_path: String, length: RunLength, compare: bool, options: Options },
+    TraceDiff { trace_a: String, trace_b: String },
+    Help
+}
+
+// Options of every subcommand, checked against the subcommand afterwards.
+#[derive(Default)]
+struct Parsed {
+    options: Options,
+    positionals: Vec<String>,
+    output_path: Option<String>,
+    frames: Option<u32>,
+    instructions: Option<u64>,
+    expect_path: Option<String>,
+    compare: bool
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let subcommand = args.get(1).map(|arg| arg.as_str()).unwrap_or("");
+    let (subcommand, rest) = match subcommand {
+        "run" | "disasm" | "asm" | "info" | "test" | "bench" | "trace-diff" | "help" => (subcommand, &args[2..]),
+        _ => ("run", args.get(1..).unwrap_or(&[]))
+    };
+
+    let mut parsed = parse_options(rest)?;
+    if parsed.positionals.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help);
+    }
+
+    let mut positionals = parsed.positionals.drain(..);
+    let first = positionals.next();
+    let second = positionals.next();
+    if positionals.next().is_some() {
+        return Err(format!("Too many arguments for {}", subcommand));
+    }
+
+    let missing = |what: &str| format!("{} expects {}", subcommand, what);
+
+    let command = match subcommand {
+        "run" if parsed.options.netplay.is_some() && first.is_none() => return Err("netplay expects a ROM, the same for both players".to_string()),
+        "run" if parsed.options.netplay.is_some() && parsed.options.gdb_port.is_some() => return Err("netplay can not be used with --gdb".to_string()),
+        "run" if second.is_none() => Command::Run { rom_path: first, options: parsed.options },
+        "disasm" if second.is_none() => Command::Disasm {
+            rom_path: first.ok_or_else(|| missing("a ROM"))?,
+            coverage_path: parsed.options.coverage_path
+        },
+        "asm" if second.is_none() => {
+            let source_path = first.ok_or_else(|| missing("a source file"))?;
+            let output_path = parsed.output_path.unwrap_or_else(|| PathBuf::from(&source_path).with_extension("ch8").to_string_lossy().into_owned());
+            Command::Asm { source_path, output_path }
+        },
+        "info" if second.is_none() => Command::Info { rom_path: first.ok_or_else(|| missing("a ROM"))?, options: parsed.options },
+        "test" if second.is_none() => Command::Test {
+            rom_path: first.ok_or_else(|| missing("a ROM"))?,
+            frames: parsed.frames.unwrap_or(DEFAULT_TEST_FRAMES),
+            expect_path: parsed.expect_path,
+            options: parsed.options
+        },
+        "bench" if second.is_none() => Command::Bench {
+            rom_path: first.ok_or_else(|| missing("a ROM"))?,
+            length: match (parsed.frames, parsed.instructions) {
+                (Some(_), Some(_)) => return Err("bench expects either --frames or --instructions".to_string()),
+                (_, Some(instructions)) => RunLength::Instructions(instructions),
+                (frames, None) => RunLength::Frames(frames.unwrap_or(DEFAULT_BENCH_FRAMES))
+            },
+            compare: parsed.compare,
+            options: parsed.options
+        },
+        "trace-diff" => Command::TraceDiff {
+            trace_a: first.ok_or_else(|| missing("two traces"))?,
+            trace_b: second.ok_or_else(|| missing("two traces"))?
+        },
+        "help" => Command::Help,
+        _ => return Err(format!("Too many arguments for {}", subcommand))
+    };
+
+    Ok(command)
+}
+
+fn parse_options(args: &[String]) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+    let options = &mut parsed.options;
+
+    let mut i = 0;
+    while i < args.len() {
+        let option = args[i].as_str();
+        let value = args.get(i + 1);
+
+        match (option, value) {
+            ("--gdb", Some(port)) => {
+                options.gdb_port = Some(port.parse().map_err(|_| format!("Invalid GDB port -> {}", port))?);
+                i += 1;
+            },
+            ("--watch-read", Some(range)) | ("--watch-write", Some(range)) | ("--watch-change", Some(range)) => {
+                let kind = match option {
+                    "--watch-read" => WatchKind::Read,
+                    "--watch-write" => WatchKind::Write,
+                    _ => WatchKind::Change
+                };
+                let (start, end) = parse_address_range(range).ok_or_else(|| format!("Invalid address range -> {}", range))?;
+                options.watchpoints.push((start, end, kind));
+                i += 1;
+            },
+            ("--access-log", Some(path)) => {
+                options.access_log_path = Some(path.clone());
+                i += 1;
+            },
+            ("--trace", Some(path)) => {
+                options.trace_path = Some(path.clone());
+                i += 1;
+            },
+            ("--trace-format", Some(format)) => {
+                options.trace_format = match format.as_str() {
+                    "text" => TraceFormat::Text,
+                    "binary" => TraceFormat::Binary,
+                    _ => return Err(format!("Unknown trace format -> {}", format))
+                };
+                i += 1;
+            },
+            ("--trace-range", Some(range)) => {
+                options.trace_filter.range = Some(parse_address_range(range).ok_or_else(|| format!("Invalid address range -> {}", range))?);
+                i += 1;
+            },
+            ("--trace-opcodes", Some(classes)) => {
+                let parsed_classes: Option<Vec<u8>> = classes.split(',').map(|class| u8::from_str_radix(class, 16).ok().filter(|class| *class <= 0xF)).collect();
+                options.trace_filter.classes = Some(parsed_classes.ok_or_else(|| format!("Invalid opcode classes -> {}", classes))?);
+                i += 1;
+            },
+            ("--profile", _) => options.profile = true,
+            ("--profile-output", Some(path)) => {
+                options.profile_path = Some(path.clone());
+                i += 1;
+            },
+            ("--coverage", Some(path)) => {
+                options.coverage_path = Some(path.clone());
+                i += 1;
+            },
+            ("--coverage-image", Some(path)) => {
+                options.coverage_image_path = Some(path.clone());
+                i += 1;
+            },
+            ("--watch", _) => options.watch = true,
+            ("--keep-state", _) => options.keep_state = true,
+            ("--options", Some(path)) => {
+                options.octo_options_path = Some(path.clone());
+                i += 1;
+            },
+            ("--host", Some(_)) | ("--join", Some(_)) if options.netplay.is_some() => {
+                return Err("Choose either --host or --join".to_string());
+            },
+            ("--host", Some(port)) => {
+                options.netplay = Some(NetplayRole::Host(port.parse().map_err(|_| format!("Invalid netplay port -> {}", port))?));
+                i += 1;
+            },
+            ("--join", Some(address)) => {
+                options.netplay = Some(NetplayRole::Join(address.clone()));
+                i += 1;
+            },
+            ("--own-keys", Some(keys)) => {
+                options.own_keys = Some(netplay::parse_keys(keys).ok_or_else(|| format!("Invalid keys, expected hexadecimal digits such as 14CD -> {}", keys))?);
+                i += 1;
+            },
+            ("--input-delay", Some(frames)) => {
+                let frames = frames.parse().ok().filter(|frames| *frames <= MAX_INPUT_DELAY);
+                options.input_delay = Some(frames.ok_or_else(|| format!("The input delay must be between 0 and {} frames", MAX_INPUT_DELAY))?);
+                i += 1;
+            },
+            ("--rom-dir", Some(dir)) => {
+                options.rom_dir = Some(PathBuf::from(dir));
+                i += 1;
+            },
+            ("--scale", Some(scale)) => {
+                let scale = scale.parse().ok().filter(|scale| (1..=MAX_SCALE).contains(scale));
+                options.settings.scale = Some(scale.ok_or_else(|| format!("The scale must be between 1 and {}", MAX_SCALE))?);
+                i += 1;
+            },
+            ("--palette", Some(palette)) => {
+                let (foreground, background) = palette.split_once(',')
+                    .filter(|(foreground, background)| parse_color(foreground).is_some() && parse_color(background).is_some())
+                    .ok_or_else(|| format!("Invalid palette, expected #RRGGBB,#RRGGBB -> {}", palette))?;
+                options.settings.foreground = Some(foreground.to_string());
+                options.settings.background = Some(background.to_string());
+                i += 1;
+            },
+            ("--speed", Some(speed)) => {
+                options.settings.speed = Some(speed.parse().ok().filter(|speed| *speed > 0).ok_or_else(|| format!("Invalid speed -> {}", speed))?);
+                i += 1;
+            },
+            ("--quirks", Some(preset)) => {
+                if Quirks::preset(preset).is_none() {
+                    return Err(format!("Unknown quirk preset -> {}", preset));
+                }
+                options.settings.quirks = Some(preset.clone());
+                i += 1;
+            },
+            ("--key", Some(binding)) => {
+                let (key, key_name) = binding.split_once('=').ok_or_else(|| format!("Invalid key binding, expected KEY=NAME -> {}", binding))?;
+                Keymap::new().bind(key, key_name)?;
+                options.settings.keys.insert(key.to_string(), key_name.to_string());
+                i += 1;
+            },
+            ("--audio", Some(audio)) => {
+                options.settings.audio = match audio.as_str() {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => return Err(format!("Invalid audio setting, expected on or off -> {}", audio))
+                };
+                i += 1;
+            },
+            ("--seed", Some(seed)) => {
+                options.settings.seed = Some(seed.parse().map_err(|_| format!("Invalid seed -> {}", seed))?);
+                i += 1;
+            },
+            ("--font", Some(font)) => {
+                if FontStyle::from_name(font).is_none() {
+                    return Err(format!("Unknown font -> {}", font));
+                }
+                options.settings.font = Some(font.clone());
+                i += 1;
+            },
+            ("--font-address", Some(addr)) => {
+                let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16).ok().filter(|addr| *addr <= MAX_FONT_ADDRESS);
+                options.settings.font_address = Some(addr.ok_or_else(|| format!("The font address must be between 0 and 0x{:03X}", MAX_FONT_ADDRESS))?);
+                i += 1;
+            },
+            ("--memory", Some(memory)) => {
+                if config::memory_size(memory).is_none() {
+                    return Err(format!("Unknown memory -> {}", memory));
+                }
+                options.settings.memory = Some(memory.clone());
+                i += 1;
+            },
+            ("--out-of-range", Some(policy)) => {
+                if OutOfRange::from_name(policy).is_none() {
+                    return Err(format!("Unknown out of range policy -> {}", policy));
+                }
+                options.settings.out_of_range = Some(policy.clone());
+                i += 1;
+            },
+            ("--protect-interpreter", _) => options.settings.protect_interpreter = Some(true),
+            ("-o", Some(path)) | ("--output", Some(path)) => {
+                parsed.output_path = Some(path.clone());
+                i += 1;
+            },
+            ("--frames", Some(frames)) => {
+                parsed.frames = Some(frames.parse().map_err(|_| format!("Invalid frame count -> {}", frames))?);
+                i += 1;
+            },
+            ("--instructions", Some(instructions)) => {
+                parsed.instructions = Some(instructions.parse().map_err(|_| format!("Invalid instruction count -> {}", instructions))?);
+                i += 1;
+            },
+            ("--compare", _) => parsed.compare = true,
+            ("--expect", Some(path)) => {
+                parsed.expect_path = Some(path.clone());
+                i += 1;
+            },
+            ("--help", _) | ("-h", _) => parsed.positionals.push(option.to_string()),
+            ("-", _) => parsed.positionals.push(option.to_string()),
+            _ if !option.starts_with('-') => parsed.positionals.push(option.to_string()),
+            _ => return Err(format!("Unknown or incomplete option -> {}", option))
+        }
+        i += 1;
+    }
+
+    Ok(parsed)
+}
+
+fn parse_address_range(range: &str) -> Option<(u16, u16)> {
+    let parse = |addr: &str| u16::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+
+    match range.split_once('-') {
+        Some((start, end)) => Some((parse(start)?, parse(end)?)),
+        None => parse(range).map(|addr| (addr, addr))
+    }
+}
+
+pub fn print_usage() {
+    println!("Usage : ./tlmc [run] [options] [chip8-rom|rom.gz|roms.zip|-]");
+    println!("       ./tlmc disasm <chip8-rom> [--coverage <file>]");
+    println!("       ./tlmc asm <source> [-o <chip8-rom>]");
+    println!("       ./tlmc info <chip8-rom>");
+    println!("       ./tlmc test <chip8-rom> [--frames <count>] [--expect <screen-file>]");
+    println!("       ./tlmc bench <chip8-rom> [--frames <count>|--instructions <count>] [--compare]");
+    println!("       ./tlmc trace-diff <trace-a> <trace-b>");
+    println!();
+    println!("Emulation options (override the configuration file) :");
+    println!("  --scale <pixels>          size of a CHIP-8 pixel on screen");
+    println!("  --palette <fg>,<bg>       colours as #RRGGBB,#RRGGBB");
+    println!("  --speed <count>           instructions per frame");
+    println!("  --quirks <preset>         default, chip8, schip or xochip");
+    println!("  --key <key>=<name>        bind a CHIP-8 key to an SDL key name (e.g. 5=Up)");
+    println!("  --audio <on|off>          enable or mute the sound");
+    println!("  --seed <number>           seed of the CXNN random numbers");
+    println!("  --font <style>            vip, dream6800, eti660, chip48 (default) or octo");
+    println!("  --font-address <addr>     address of the font in hexadecimal (default : 0)");
+    println!("  --memory <size>           classic (4 KiB, default) or xochip (64 KiB)");
+    println!("  --out-of-range <policy>   accesses beyond memory : ignore (default), wrap or fault");
+    println!("  --protect-interpreter     drop the writes of the program below 0x200");
+    println!("  --options <file>          Octo options file (default : the ROM path with a .json extension)");
+    println!();
+    println!("Run options :");
+    println!("  --gdb <port>              wait for a GDB client on 127.0.0.1:<port>");
+    println!("  --watch-read <range>      pause when the program reads the range (e.g. 300-30F)");
+    println!("  --watch-write <range>     pause when the program writes the range");
+    println!("  --watch-change <range>    pause when the program changes a value in the range");
+    println!("  --access-log <file>       write the per-address memory access log at exit");
+    println!("  --trace <file>            write one line per executed instruction");
+    println!("  --trace-format <format>   text (default) or binary");
+    println!("  --trace-range <range>     only trace instructions in the PC range");
+    println!("  --trace-opcodes <classes> only trace these opcode classes (e.g. 8,D,F)");
+    println!("  --profile                 print an execution profile at exit");
+    println!("  --profile-output <file>   export the execution profile as .json or .csv");
+    println!("  --coverage <file>         write the code/data coverage map at exit");
+    println!("  --coverage-image <file>   write the coverage map as a BMP image at exit");
+    println!("  --watch                   reload the ROM when the file changes");
+    println!("  --keep-state              keep the registers and memory on reload when the code layout allows");
+    println!("  --rom-dir <dir>           directory listed by the launcher");
+    println!();
+    println!("Netplay options (both players run the same ROM) :");
+    println!("  --host <port>             play with a second player joining on the port");
+    println!("  --join <address:port>     join a host, which sends its state and settings");
+    println!("  --own-keys <keys>         keys of the local player (default : 01234567 for the host, 89ABCDEF for the guest)");
+    println!("  --input-delay <frames>    frames between a key press and its effect, set by the host (default : 2)");
+    println!();
+    println!("Without a ROM, TLMC opens the launcher.");
+}
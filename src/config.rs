@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::font::{FontStyle, MAX_FONT_ADDRESS};
+use crate::instruction::InstructionSet;
+use crate::quirks::Quirks;
+use crate::ram::{MemoryMap, OutOfRange, CLASSIC_MEMORY_SIZE, XO_MEMORY_SIZE};
+use crate::rom_database::{self, RomInfo};
+
+// Emulation settings, from the configuration file, the ROM database or the command line.
+// Unset values leave the previous ones unchanged when merged.
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct Settings {
+    pub scale: Option<u32>,
+    pub speed: Option<u32>,
+    pub quirks: Option<String>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub audio: Option<bool>,
+    pub seed: Option<u64>,
+    pub font: Option<String>,
+    pub font_address: Option<u16>,
+    // "classic" (4 KiB) or "xochip" (64 KiB).
+    pub memory: Option<String>,
+    // "ignore", "wrap" or "fault".
+    pub out_of_range: Option<String>,
+    pub protect_interpreter: Option<bool>,
+    // Individual quirks, applied over the preset.
+    #[serde(default)]
+    pub quirk_flags: QuirkFlags,
+    // Hexadecimal key -> SDL key name, e.g. "5" = "Up".
+    #[serde(default)]
+    pub keys: HashMap<String, String>
+}
+
+impl Settings {
+    // Values set in other replace the ones of self.
+    pub fn merge(&mut self, other: &Settings) {
+        if other.scale.is_some() {
+            self.scale = other.scale;
+        }
+        if other.speed.is_some() {
+            self.speed = other.speed;
+        }
+        if other.quirks.is_some() {
+            self.quirks = other.quirks.clone();
+        }
+        if other.foreground.is_some() {
+            self.foreground = other.foreground.clone();
+        }
+        if other.background.is_some() {
+            self.background = other.background.clone();
+        }
+        if other.audio.is_some() {
+            self.audio = other.audio;
+        }
+        if other.seed.is_some() {
+            self.seed = other.seed;
+        }
+        if other.font.is_some() {
+            self.font = other.font.clone();
+        }
+        if other.font_address.is_some() {
+            self.font_address = other.font_address;
+        }
+        if other.memory.is_some() {
+            self.memory = other.memory.clone();
+        }
+        if other.out_of_range.is_some() {
+            self.out_of_range = other.out_of_range.clone();
+        }
+        if other.protect_interpreter.is_some() {
+            self.protect_interpreter = other.protect_interpreter;
+        }
+        for (key, key_name) in &other.keys {
+            self.keys.insert(key.clone(), key_name.clone());
+        }
+        self.quirk_flags.merge(&other.quirk_flags);
+    }
+
+    // The preset, with the individual quirks applied over it.
+    pub fn get_quirks(&self) -> Result<Quirks, String> {
+        let mut quirks = match &self.quirks {
+            Some(name) => Quirks::preset(name).ok_or_else(|| format!("Unknown quirk preset -> {}", name))?,
+            None => Quirks::default()
+        };
+        self.quirk_flags.apply(&mut quirks);
+
+        Ok(quirks)
+    }
+
+    // SUPER-CHIP with the schip and xochip presets.
+    pub fn get_instruction_set(&self) -> InstructionSet {
+        self.quirks.as_deref().map_or(InstructionSet::default(), InstructionSet::from_preset)
+    }
+
+    pub fn get_font(&self) -> Result<(FontStyle, u16), String> {
+        let font = match &self.font {
+            Some(name) => FontStyle::from_name(name).ok_or_else(|| format!("Unknown font -> {}", name))?,
+            None => FontStyle::default()
+        };
+
+        let address = self.font_address.unwrap_or(0x0);
+        if address > MAX_FONT_ADDRESS {
+            return Err(format!("The font address must be at most 0x{:03X}", MAX_FONT_ADDRESS));
+        }
+
+        Ok((font, address))
+    }
+
+    pub fn get_memory_map(&self) -> Result<MemoryMap, String> {
+        let mut map = MemoryMap::default();
+
+        if let Some(memory) = &self.memory {
+            map.size = memory_size(memory).ok_or_else(|| format!("Unknown memory -> {}", memory))?;
+        }
+        if let Some(policy) = &self.out_of_range {
+            map.out_of_range = OutOfRange::from_name(policy).ok_or_else(|| format!("Unknown out of range policy -> {}", policy))?;
+        }
+        map.protect_interpreter = self.protect_interpreter.unwrap_or(false);
+
+        Ok(map)
+    }
+}
+
+pub fn memory_size(name: &str) -> Option<usize> {
+    match name {
+        "classic" => Some(CLASSIC_MEMORY_SIZE),
+        "xochip" => Some(XO_MEMORY_SIZE),
+        _ => None
+    }
+}
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct QuirkFlags {
+    pub vf_reset: Option<bool>,
+    pub shift: Option<bool>,
+    pub load_store: Option<bool>,
+    pub jump: Option<bool>,
+    pub clip: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub vf_order: Option<bool>
+}
+
+impl QuirkFlags {
+    fn merge(&mut self, other: &QuirkFlags) {
+        self.vf_reset = other.vf_reset.or(self.vf_reset);
+        self.shift = other.shift.or(self.shift);
+        self.load_store = other.load_store.or(self.load_store);
+        self.jump = other.jump.or(self.jump);
+        self.clip = other.clip.or(self.clip);
+        self.display_wait = other.display_wait.or(self.display_wait);
+        self.vf_order = other.vf_order.or(self.vf_order);
+    }
+
+    // The flags that are set, e.g. ["shift on", "clip off"].
+    pub fn describe(&self) -> Vec<String> {
+        let flags = [
+            ("vf_reset", self.vf_reset),
+            ("shift", self.shift),
+            ("load_store", self.load_store),
+            ("jump", self.jump),
+            ("clip", self.clip),
+            ("display_wait", self.display_wait),
+            ("vf_order", self.vf_order)
+        ];
+
+        flags.iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{} {}", name, if value {"on"} else {"off"})))
+            .collect()
+    }
+
+    fn apply(&self, quirks: &mut Quirks) {
+        quirks.vf_reset = self.vf_reset.unwrap_or(quirks.vf_reset);
+        quirks.shift = self.shift.unwrap_or(quirks.shift);
+        quirks.load_store = self.load_store.unwrap_or(quirks.load_store);
+        quirks.jump = self.jump.unwrap_or(quirks.jump);
+        quirks.clip = self.clip.unwrap_or(quirks.clip);
+        quirks.display_wait = self.display_wait.unwrap_or(quirks.display_wait);
+        quirks.vf_order = self.vf_order.unwrap_or(quirks.vf_order);
+    }
+}
+
+impl From<&RomInfo> for Settings {
+    fn from(info: &RomInfo) -> Settings {
+        Settings {
+            speed: info.instructions_per_frame,
+            quirks: info.quirks.clone().or_else(|| info.platform.clone()),
+            foreground: info.foreground.clone(),
+            background: info.background.clone(),
+            keys: info.keys.clone(),
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub global: Settings,
+    // Per-ROM sections, keyed by file name or SHA-1 : [roms."pong.ch8"].
+    #[serde(default)]
+    roms: HashMap<String, Settings>
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let Some(path) = config_path() else {
+            return Config::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|why| {
+                println!("Error : Unable to parse the configuration file {} !", path.display());
+                println!("Why -> {}", why);
+                Config::default()
+            }),
+            Err(_) => Config::default()
+        }
+    }
+
+    // The section of a ROM, looked up by SHA-1 first, then by file name.
+    pub fn rom_section(&self, rom_path: &str, sha1: Option<&str>) -> Option<&Settings> {
+        let by_hash = sha1.and_then(|sha1| self.roms.iter().find(|(name, _)| name.eq_ignore_ascii_case(sha1)));
+        let file_name = Path::new(rom_path).file_name().and_then(|name| name.to_str());
+        let by_name = file_name.and_then(|file_name| self.roms.get(file_name));
+
+        by_hash.map(|(_, settings)| settings).or(by_name)
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    rom_database::config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// "#RRGGBB" or "RRGGBB", as (red, green, blue).
+pub fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write, ErrorKind};
+
+use crate::cpu::Cpu;
+use crate::ram::XO_MEMORY_SIZE;
+use crate::instruction::{decode, Instruction};
+
+pub const EXECUTED: u8 = 0x1;
+pub const SPRITE: u8 = 0x2;
+pub const DATA_READ: u8 = 0x4;
+pub const DATA_WRITE: u8 = 0x8;
+
+static FLAG_NAMES: [(u8, &str); 4] = [
+    (EXECUTED, "code"),
+    (SPRITE, "sprite"),
+    (DATA_READ, "read"),
+    (DATA_WRITE, "write"),
+];
+
+// Image layout : one 4x4 block per byte, 64 bytes per row.
+const IMAGE_COLUMNS: usize = 64;
+const IMAGE_BLOCK: usize = 4;
+
+#[derive(Clone)]
+pub struct Coverage {
+    flags: Vec<u8>
+}
+
+impl Coverage {
+    pub fn new(memory_size: usize) -> Coverage {
+        Coverage {
+            flags: vec![0; memory_size]
+        }
+    }
+
+    pub fn get_flags(&self, addr: u16) -> u8 {
+        self.flags.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    fn mark(&mut self, start: u16, len: u16, flag: u8) {
+        for addr in start..start.saturating_add(len) {
+            if let Some(flags) = self.flags.get_mut(addr as usize) {
+                *flags |= flag;
+            }
+        }
+    }
+
+    // Called before each instruction is executed.
+    pub fn record(&mut self, pc: u16, opcode: u16, cpu: &Cpu) {
+        self.mark(pc, 2, EXECUTED);
+
+        let index_reg = cpu.get_index_reg();
+
+        match decode(opcode, cpu.get_instruction_set()) {
+            Ok(Instruction::LdB(_)) => self.mark(index_reg, 3, DATA_WRITE),
+            Ok(Instruction::LdIVx(x)) => self.mark(index_reg, x as u16 + 1, DATA_WRITE),
+            Ok(Instruction::LdVxI(x)) => self.mark(index_reg, x as u16 + 1, DATA_READ),
+            Ok(Instruction::Drw(_, _, n)) => self.mark(index_reg, n as u16, SPRITE),
+            _ => ()
+        }
+    }
+
+    // Writes ranges of bytes sharing the same flags, e.g. "200-2A5 code".
+    pub fn save_annotations(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "# TLMC coverage map : <start>-<end> <code|sprite|read|write>[+...]")?;
+
+        let mut start = 0;
+        while start < self.flags.len() {
+            let flags = self.flags[start];
+            let mut end = start;
+            while end + 1 < self.flags.len() && self.flags[end + 1] == flags {
+                end += 1;
+            }
+
+            if flags != 0 {
+                let names: Vec<&str> = FLAG_NAMES.iter().filter(|(flag, _)| flags & flag != 0).map(|(_, name)| *name).collect();
+                writeln!(writer, "{:03X}-{:03X} {}", start, end, names.join("+"))?;
+            }
+
+            start = end + 1;
+        }
+
+        writer.flush()
+    }
+
+    pub fn load_annotations(path: &str) -> io::Result<Coverage> {
+        let mut coverage = Coverage::new(XO_MEMORY_SIZE);
+        let invalid = |line: &str| io::Error::new(ErrorKind::InvalidData, format!("invalid coverage line -> {}", line));
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (range, names) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = u16::from_str_radix(start, 16).map_err(|_| invalid(line))?;
+            let end = u16::from_str_radix(end, 16).map_err(|_| invalid(line))?;
+
+            let mut flags = 0;
+            for name in names.trim().split('+') {
+                match FLAG_NAMES.iter().find(|(_, flag_name)| *flag_name == name) {
+                    Some((flag, _)) => flags |= flag,
+                    None => return Err(invalid(line))
+                }
+            }
+
+            if end < start {
+                return Err(invalid(line));
+            }
+            coverage.mark(start, end - start + 1, flags);
+        }
+
+        Ok(coverage)
+    }
+
+    // Writes the map as a 24-bit BMP image.
+    pub fn save_image(&self, path: &str) -> io::Result<()> {
+        let width = IMAGE_COLUMNS * IMAGE_BLOCK;
+        let height = self.flags.len() / IMAGE_COLUMNS * IMAGE_BLOCK;
+        let row_size = width * 3;
+        let image_size = row_size * height;
+
+        let mut data = Vec::with_capacity(54 + image_size);
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&((54 + image_size) as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&54u32.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&(width as i32).to_le_bytes());
+        // Negative height : rows are stored top to bottom.
+        data.extend_from_slice(&(-(height as i32)).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&24u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+
+        for y in 0..height {
+            for x in 0..width {
+                let addr = (y / IMAGE_BLOCK) * IMAGE_COLUMNS + x / IMAGE_BLOCK;
+                let (r, g, b) = flags_color(self.flags[addr]);
+                data.extend_from_slice(&[b, g, r]);
+            }
+        }
+
+        File::create(path)?.write_all(&data)
+    }
+}
+
+fn flags_color(flags: u8) -> (u8, u8, u8) {
+    let data = flags & (DATA_READ | DATA_WRITE) != 0;
+
+    if flags & EXECUTED != 0 && (data || flags & SPRITE != 0) {
+        (255, 0, 255)
+    } else if flags & EXECUTED != 0 {
+        (0, 200, 0)
+    } else if flags & DATA_WRITE != 0 {
+        (230, 40, 40)
+    } else if flags & DATA_READ != 0 {
+        (240, 220, 0)
+    } else if flags & SPRITE != 0 {
+        (40, 120, 255)
+    } else {
+        (30, 30, 30)
+    }
+}
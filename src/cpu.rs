@@ -1,30 +1,81 @@
-use rand::{Rng, rngs::ThreadRng};
+use std::fmt;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
 use crate::display::Display;
-use crate::ram::Ram;
-use crate::sound_system::SoundSystem;
+use crate::bus::Bus;
+use crate::font::FONT_SIZE;
+use crate::instruction::{DecodeError, Instruction, InstructionSet};
+use crate::quirks::Quirks;
+use crate::save_state::{StateError, StateReader, StateWriter};
 
 const REGISTERS_COUNT: usize = 16;
 const STACK_SIZE: usize = 16;
 const KEYS_COUNT: usize = 16;
 const PC_START: u16 = 0x200;
+const SCREEN_WIDTH: u8 = 64;
+const SCREEN_HEIGHT: u8 = 32;
+
+// Why an instruction could not run. The CPU is left as it was, with the PC on the
+// instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CpuError {
+    // Not a CHIP-8 instruction, 0NNN machine code routines included.
+    UnknownOpcode(u16),
+    // CALL with the 16 levels of the stack in use.
+    StackOverflow,
+    // RET outside of any subroutine.
+    StackUnderflow
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode(opcode) => write!(f, "Unknown opcode -> 0x{:04X}", opcode),
+            CpuError::StackOverflow => write!(f, "Stack overflow, more than {} nested subroutines", STACK_SIZE),
+            CpuError::StackUnderflow => write!(f, "Stack underflow, return outside of a subroutine")
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+impl From<DecodeError> for CpuError {
+    fn from(why: DecodeError) -> CpuError {
+        CpuError::UnknownOpcode(why.opcode)
+    }
+}
 
-pub struct Cpu<'a> {
+#[derive(Clone)]
+pub struct Cpu {
     v: [u8; REGISTERS_COUNT],
     stack: [u16; STACK_SIZE],
     keys: [u8; KEYS_COUNT],
     index_reg: u16,
     delay_timer: u8,
     sound_timer: u8,
+    // From 0 to STACK_SIZE.
     sp: u16,
+    // The PC and I wrap around at 0xFFFF.
     pc: u16,
-    rng: ThreadRng,
-    sound_system: SoundSystem<'a>,
-    draw_flag: bool
+    // The generator of StdRng, whose position can be saved.
+    rng: ChaCha12Rng,
+    draw_flag: bool,
+    quirks: Quirks,
+    instruction_set: InstructionSet,
+    vblank: bool,
+    font_address: u16
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
 }
 
-impl <'a> Cpu<'a> {
-    pub fn new() -> Cpu<'a> {
+impl Cpu {
+    pub fn new() -> Cpu {
         Cpu {
             v: [0x0; REGISTERS_COUNT],
             stack: [0x0; STACK_SIZE],
@@ -34,20 +85,57 @@ impl <'a> Cpu<'a> {
             sound_timer: 0x0,
             sp: 0x0,
             pc: PC_START,
-            rng: rand::thread_rng(),
-            sound_system: SoundSystem::new(),
-            draw_flag: false
+            rng: ChaCha12Rng::from_entropy(),
+            draw_flag: false,
+            quirks: Quirks::default(),
+            instruction_set: InstructionSet::default(),
+            vblank: false,
+            font_address: 0x0
         }
     }
 
+    // Power-on state. The quirks, the instruction set and the font address are kept.
+    pub fn reset(&mut self) {
+        self.v = [0x0; REGISTERS_COUNT];
+        self.stack = [0x0; STACK_SIZE];
+        self.keys = [0x0; KEYS_COUNT];
+        self.index_reg = 0x0;
+        self.delay_timer = 0x0;
+        self.sound_timer = 0x0;
+        self.sp = 0x0;
+        self.pc = PC_START;
+        self.draw_flag = true;
+        self.vblank = false;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    pub fn set_font_address(&mut self, font_address: u16) {
+        self.font_address = font_address;
+    }
+
+    pub fn get_font_address(&self) -> u16 {
+        self.font_address
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.instruction_set = instruction_set;
+    }
+
+    pub fn get_instruction_set(&self) -> InstructionSet {
+        self.instruction_set
+    }
+
     pub fn update_timers(&mut self) {
+        self.vblank = true;
         if self.delay_timer > 0x0 {self.delay_timer -= 1;}
-        if self.sound_timer > 0x0 {
-            self.sound_timer -= 1;
-            if self.sound_timer == 0x1 {
-                self.sound_system.play_beep_sound();
-            }
-        }
+        if self.sound_timer > 0x0 {self.sound_timer -= 1;}
     }
 
     pub fn key(&mut self, n: u8, is_down: bool) {
@@ -66,155 +154,280 @@ impl <'a> Cpu<'a> {
         self.draw_flag
     }
 
+    pub fn get_register(&self, reg_index: u8) -> u8 {
+        self.read_reg(reg_index)
+    }
+
+    pub fn get_index_reg(&self) -> u16 {
+        self.index_reg
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn get_sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_register(&mut self, reg_index: u8, reg_value: u8) {
+        self.write_reg(reg_index, reg_value);
+    }
+
+    pub fn set_index_reg(&mut self, value: u16) {
+        self.index_reg = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value.min(STACK_SIZE as u16);
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn key_is_down(&self, n: u8) -> bool {
+        self.keys[n as usize] != 0
+    }
+
+    // The draw flag belongs to the renderer and is not saved.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.v);
+        for addr in self.stack {
+            writer.u16(addr);
+        }
+        writer.bytes(&self.keys);
+        writer.u16(self.index_reg);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.u16(self.sp);
+        writer.u16(self.pc);
+        writer.bytes(&self.rng.get_seed());
+        writer.u64(self.rng.get_stream());
+        writer.u128(self.rng.get_word_pos());
+        let quirks = self.quirks;
+        for quirk in [quirks.vf_reset, quirks.shift, quirks.load_store, quirks.jump, quirks.clip, quirks.display_wait, quirks.vf_order] {
+            writer.bool(quirk);
+        }
+        writer.u8(self.instruction_set as u8);
+        writer.bool(self.vblank);
+        writer.u16(self.font_address);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.v = reader.array()?;
+        for addr in self.stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        self.keys = reader.array()?;
+        if self.keys.iter().any(|key| *key > 1) {
+            return Err(StateError::Invalid("key state"));
+        }
+        self.index_reg = reader.u16()?;
+        self.delay_timer = reader.u8()?;
+        self.sound_timer = reader.u8()?;
+        self.sp = reader.u16()?;
+        if self.sp as usize > STACK_SIZE {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        self.pc = reader.u16()?;
+        self.rng = ChaCha12Rng::from_seed(reader.array()?);
+        self.rng.set_stream(reader.u64()?);
+        self.rng.set_word_pos(reader.u128()?);
+        self.quirks = Quirks {
+            vf_reset: reader.bool()?,
+            shift: reader.bool()?,
+            load_store: reader.bool()?,
+            jump: reader.bool()?,
+            clip: reader.bool()?,
+            display_wait: reader.bool()?,
+            vf_order: reader.bool()?
+        };
+        self.instruction_set = match reader.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::Schip,
+            _ => return Err(StateError::Invalid("instruction set"))
+        };
+        self.vblank = reader.bool()?;
+        self.font_address = reader.u16()?;
+        self.draw_flag = true;
+
+        Ok(())
+    }
+
     fn write_reg(&mut self, reg_index: u8, reg_value: u8) {
         self.v[reg_index as usize] = reg_value;
     }
 
+    // The last write wins when X is F : the flag, unless the VF order quirk is set.
+    fn write_result_and_flag(&mut self, x: u8, result: u8, flag: u8) {
+        if self.quirks.vf_order {
+            self.write_reg(0xF, flag);
+            self.write_reg(x, result);
+        } else {
+            self.write_reg(x, result);
+            self.write_reg(0xF, flag);
+        }
+    }
+
     fn read_reg(&self, reg_index: u8) -> u8 {
         self.v[reg_index as usize]
     }
 
-    fn fetch_next_opcode(&self, ram: &Ram) -> u16 {
-        let msb = ram.read(self.pc) as u16;
-        let lsb = ram.read(self.pc + 1) as u16;
-
-        msb << 8 | lsb
-    }
-
-    pub fn run_instruction(&mut self, ram: &mut Ram, display: &mut Display) {
-        let opcode = self.fetch_next_opcode(ram);
-
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let n = (opcode & 0x000F) as u8;
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-
-        match opcode & 0xF000 {
-            0x0000 => {
-                match opcode & 0x00FF {
-                    0x00E0 => {
-                        display.clear();
-                        self.draw_flag = true;
-                        self.pc += 2;
-                    },
-                    0x00EE => {
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize];
-                        self.pc += 2;
-                    },
-                    _ => panic!("Unknown opcode -> 0x{:x}", opcode)
+    pub fn run_instruction<B: Bus>(&mut self, bus: &mut B, display: &mut Display) -> Result<(), CpuError> {
+        let instruction = bus.fetch_instruction(self.pc, self.instruction_set)?;
+
+        self.execute(instruction, bus, display)
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {self.pc = self.pc.wrapping_add(4);}
+        else {self.pc = self.pc.wrapping_add(2);}
+    }
+
+    pub fn execute<B: Bus>(&mut self, instruction: Instruction, bus: &mut B, display: &mut Display) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Sys(_) => return Err(CpuError::UnknownOpcode(instruction.encode())),
+            Instruction::Cls => {
+                display.clear();
+                self.draw_flag = true;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Ret => {
+                if self.sp == 0 {
+                    return Err(CpuError::StackUnderflow);
                 }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize].wrapping_add(2);
             },
-            0x1000 => {
+            Instruction::Jp(nnn) => {
                 self.pc = nnn;
             },
-            0x2000 => {
+            Instruction::Call(nnn) => {
+                if self.sp as usize >= STACK_SIZE {
+                    return Err(CpuError::StackOverflow);
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = nnn;
             },
-            0x3000 => {
-                if self.read_reg(x) == nn {self.pc += 4;}
-                else {self.pc += 2;}
+            Instruction::SeImm(x, nn) => self.skip_if(self.read_reg(x) == nn),
+            Instruction::SneImm(x, nn) => self.skip_if(self.read_reg(x) != nn),
+            Instruction::SeReg(x, y) => self.skip_if(self.read_reg(x) == self.read_reg(y)),
+            Instruction::LdImm(x, nn) => {
+                self.write_reg(x, nn);
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x4000 => {
-                if self.read_reg(x) != nn {self.pc += 4;}
-                else {self.pc += 2;}
+            Instruction::AddImm(x, nn) => {
+                self.write_reg(x, nn.wrapping_add(self.read_reg(x)));
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x5000 => {
-                if self.read_reg(x) == self.read_reg(y) {self.pc += 4;}
-                else {self.pc += 2;}
+            Instruction::LdReg(x, y) => {
+                self.write_reg(x, self.read_reg(y));
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x6000 => {
-                self.write_reg(x, nn);
-                self.pc += 2;
+            Instruction::Or(x, y) => {
+                self.write_reg(x, self.read_reg(x) | self.read_reg(y));
+                if self.quirks.vf_reset {self.write_reg(0xF, 0);}
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x7000 => {
-                self.write_reg(x, nn + self.read_reg(x));
-                self.pc += 2;
+            Instruction::And(x, y) => {
+                self.write_reg(x, self.read_reg(x) & self.read_reg(y));
+                if self.quirks.vf_reset {self.write_reg(0xF, 0);}
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x8000 => {
-                match opcode & 0x000F {
-                    0x0000 => {
-                        self.write_reg(x, self.read_reg(y));
-                        self.pc += 2;
-                    },
-                    0x0001 => {
-                        self.write_reg(x, self.read_reg(x) | self.read_reg(y));
-                        self.pc += 2;
-                    },
-                    0x0002 => {
-                        self.write_reg(x, self.read_reg(x) & self.read_reg(y));
-                        self.pc += 2;
-                    },
-                    0x0003 => {
-                        self.write_reg(x, self.read_reg(x) ^ self.read_reg(y));
-                        self.pc += 2;
-                    },
-                    0x0004 => {
-                        let r = self.read_reg(x) as u16 + self.read_reg(y) as u16;
-
-                        if r > 0xFF {self.write_reg(0xF, 1);}
-                        else {self.write_reg(0xF, 0);}
-
-                        self.write_reg(x, (r & 0xFF) as u8);
-                        self.pc += 2;
-                    },
-                    0x0005 => {
-                        if self.read_reg(x) > self.read_reg(y) {self.write_reg(0xF, 1);}
-                        else {self.write_reg(0xF, 0);}
-
-                        self.write_reg(x, self.read_reg(x) - self.read_reg(y));
-                        self.pc += 2;
-                    },
-                    0x0006 => {
-                        self.write_reg(0xF, self.read_reg(x) & 0x1);
-                        self.write_reg(x, self.read_reg(x) >> 1);
-                        self.pc += 2;
-                    },
-                    0x0007 => {
-                        if self.read_reg(y) > self.read_reg(x) {self.write_reg(0xF, 1);}
-                        else {self.write_reg(0xF, 0);}
-
-                        self.write_reg(x, self.read_reg(y) - self.read_reg(x));
-                        self.pc += 2;
-                    },
-                    0x000E => {
-                        self.write_reg(0xF, (self.read_reg(x) & 128) >> 7);
-                        self.write_reg(x, self.read_reg(x) << 1);
-                        self.pc += 2;
-                    },
-                    _ => panic!("Unknown opcode -> 0x{:x}", opcode)
-                }
+            Instruction::Xor(x, y) => {
+                self.write_reg(x, self.read_reg(x) ^ self.read_reg(y));
+                if self.quirks.vf_reset {self.write_reg(0xF, 0);}
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::AddReg(x, y) => {
+                let r = self.read_reg(x) as u16 + self.read_reg(y) as u16;
+                self.write_result_and_flag(x, (r & 0xFF) as u8, (r > 0xFF) as u8);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Sub(x, y) => {
+                let (vx, vy) = (self.read_reg(x), self.read_reg(y));
+                self.write_result_and_flag(x, vx.wrapping_sub(vy), (vx >= vy) as u8);
+                self.pc = self.pc.wrapping_add(2);
             },
-            0x9000 => {
-                if self.read_reg(x) != self.read_reg(y) {self.pc += 4;}
-                else {self.pc += 2;}
+            Instruction::Shr(x, y) => {
+                let source = if self.quirks.shift {x} else {y};
+                let value = self.read_reg(source);
+                self.write_result_and_flag(x, value >> 1, value & 0x1);
+                self.pc = self.pc.wrapping_add(2);
             },
-            0xA000 => {
+            Instruction::Subn(x, y) => {
+                let (vx, vy) = (self.read_reg(x), self.read_reg(y));
+                self.write_result_and_flag(x, vy.wrapping_sub(vx), (vy >= vx) as u8);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Shl(x, y) => {
+                let source = if self.quirks.shift {x} else {y};
+                let value = self.read_reg(source);
+                self.write_result_and_flag(x, value << 1, (value & 128) >> 7);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::SneReg(x, y) => self.skip_if(self.read_reg(x) != self.read_reg(y)),
+            Instruction::LdI(nnn) => {
                 self.index_reg = nnn;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
-            0xB000 => {
-                self.pc = self.read_reg(0x0) as u16 + nnn;
+            Instruction::JpV0(nnn) => {
+                let offset_reg = if self.quirks.jump {(nnn >> 8) as u8} else {0x0};
+                self.pc = nnn.wrapping_add(self.read_reg(offset_reg) as u16);
             },
-            0xC000 => {
-                let number = self.rng.gen_range(0x0..0xFF);
+            Instruction::Rnd(x, nn) => {
+                let number: u8 = self.rng.gen();
                 self.write_reg(x, number & nn);
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
-            0xD000 => {
-                let origin_x = self.read_reg(x);
-                let origin_y = self.read_reg(y);
+            Instruction::Drw(x, y, n) => {
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                }
+
+                let mut origin_x = self.read_reg(x);
+                let mut origin_y = self.read_reg(y);
+                if self.quirks.clip {
+                    origin_x %= SCREEN_WIDTH;
+                    origin_y %= SCREEN_HEIGHT;
+                }
 
                 self.write_reg(0xF, 0);
                 for y_coord in 0..n {
-                    let pixel = ram.read(self.index_reg + y_coord as u16);
+                    let pixel = bus.read(self.index_reg.wrapping_add(y_coord as u16));
                     for x_coord in 0..8 {
                         if pixel & (0x80 >> x_coord) != 0 {
-                            let pixel_x = (origin_x.wrapping_add(x_coord)) % 64;
-                            let pixel_y = (origin_y.wrapping_add(y_coord)) % 32;
+                            if self.quirks.clip && (origin_x as u16 + x_coord as u16 >= SCREEN_WIDTH as u16 || origin_y as u16 + y_coord as u16 >= SCREEN_HEIGHT as u16) {
+                                continue;
+                            }
+
+                            let pixel_x = (origin_x.wrapping_add(x_coord)) % SCREEN_WIDTH;
+                            let pixel_y = (origin_y.wrapping_add(y_coord)) % SCREEN_HEIGHT;
 
                             if display.pixel_is_on_at(pixel_x, pixel_y) {
                                 display.set_pixel_state_at(pixel_x, pixel_y, false);
@@ -227,88 +440,715 @@ impl <'a> Cpu<'a> {
                 }
 
                 self.draw_flag = true;
-                self.pc += 2;
+                self.pc = self.pc.wrapping_add(2);
             },
-            0xE000 => {
-                match opcode & 0x00FF {
-                    0x009E => {
-                        if self.keys[self.read_reg(x) as usize] == 1 {self.pc += 4;}
-                        else {self.pc += 2;}
-                    },
-                    0x00A1 => {
-                        if self.keys[self.read_reg(x) as usize] == 0 {self.pc += 4;}
-                        else {self.pc += 2;}
-                    },
-                    _ => panic!("Unknown opcode -> 0x{:x}", opcode)
-                }
+            Instruction::Skp(x) => self.skip_if(self.keys[(self.read_reg(x) & 0xF) as usize] == 1),
+            Instruction::Sknp(x) => self.skip_if(self.keys[(self.read_reg(x) & 0xF) as usize] == 0),
+            Instruction::LdVxDt(x) => {
+                self.write_reg(x, self.delay_timer);
+                self.pc = self.pc.wrapping_add(2);
             },
-            0xF000 => {
-                match opcode & 0x00FF {
-                    0x0007 => {
-                    self.write_reg(x, self.delay_timer);
-                    self.pc += 2;
-                    },
-                    0x000A => {
-                        let mut pressed = false;
-                        for i in 0..KEYS_COUNT as u8 {
-                            if self.keys[i as usize] != 0 {
-                                self.write_reg(x, i);
-                                pressed = true;
-                            }
-                        }
-
-                        if !pressed {
-                            return;
-                        }
+            Instruction::LdVxK(x) => {
+                let mut pressed = false;
+                for i in 0..KEYS_COUNT as u8 {
+                    if self.keys[i as usize] != 0 {
+                        self.write_reg(x, i);
+                        pressed = true;
+                    }
+                }
 
-                        self.pc += 2;
-                    },
-                    0x0015 => {
-                        self.delay_timer = self.read_reg(x);
-                        self.pc += 2;
-                    },
-                    0x0018 => {
-                        self.sound_timer = self.read_reg(x);
-                        self.pc += 2;
-                    },
-                    0x001E => {
-                        self.index_reg += self.read_reg(x) as u16;
-                        self.pc += 2;
-                    },
-                    0x0029 => {
-                        self.index_reg = self.read_reg(x) as u16 * 5;
-                        self.pc += 2;
-                    },
-                    0x0033 => {
-                        // Hundreds.
-                        ram.write(self.index_reg, self.read_reg(x) / 100);
-                        // Tens.
-                        ram.write(self.index_reg + 1, (self.read_reg(x) % 100) / 10);
-                        // Units.
-                        ram.write(self.index_reg + 2, self.read_reg(x) % 10);
-
-                        self.pc += 2;
-                    },
-                    0x0055 => {
-                        for i in 0..(x + 1) {
-                            let reg_value = self.read_reg(i);
-                            ram.write(self.index_reg + i as u16, reg_value);
-                        }
+                if !pressed {
+                    return Ok(());
+                }
 
-                        self.pc += 2;
-                    },
-                    0x0065 => {
-                        for i in 0..(x + 1) {
-                            let reg_value = ram.read(self.index_reg + i as u16);
-                            self.write_reg(i, reg_value);
-                        }
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdDtVx(x) => {
+                self.delay_timer = self.read_reg(x);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdStVx(x) => {
+                self.sound_timer = self.read_reg(x);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::AddI(x) => {
+                self.index_reg = self.index_reg.wrapping_add(self.read_reg(x) as u16);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdF(x) => {
+                // only the low nibble selects a glyph.
+                self.index_reg = self.font_address.wrapping_add((self.read_reg(x) & 0xF) as u16 * 5);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdHf(x) => {
+                // the digits follow the 4x5 font, A to F are not part of it.
+                self.index_reg = self.font_address.wrapping_add(FONT_SIZE as u16 + (self.read_reg(x) & 0xF) as u16 * 10);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdB(x) => {
+                // Hundreds.
+                bus.write(self.index_reg, self.read_reg(x) / 100);
+                // Tens.
+                bus.write(self.index_reg.wrapping_add(1), (self.read_reg(x) % 100) / 10);
+                // Units.
+                bus.write(self.index_reg.wrapping_add(2), self.read_reg(x) % 10);
 
-                        self.pc += 2;
-                    },
-                    _ => panic!("Unknown opcode -> 0x{:x}", opcode)
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdIVx(x) => {
+                for i in 0..(x + 1) {
+                    let reg_value = self.read_reg(i);
+                    bus.write(self.index_reg.wrapping_add(i as u16), reg_value);
                 }
+
+                if !self.quirks.load_store {self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);}
+                self.pc = self.pc.wrapping_add(2);
             },
-            _ => panic!("Unknown opcode -> 0x{:x}", opcode)
+            Instruction::LdVxI(x) => {
+                for i in 0..(x + 1) {
+                    let reg_value = bus.read(self.index_reg.wrapping_add(i as u16));
+                    self.write_reg(i, reg_value);
+                }
+
+                if !self.quirks.load_store {self.index_reg = self.index_reg.wrapping_add(x as u16 + 1);}
+                self.pc = self.pc.wrapping_add(2);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram::{MemoryMap, Ram, XO_MEMORY_SIZE};
+
+    struct Machine {
+        cpu: Cpu,
+        ram: Ram,
+        display: Display
+    }
+
+    impl Machine {
+        fn new() -> Machine {
+            Machine::with_quirks(Quirks::default())
+        }
+
+        fn with_quirks(quirks: Quirks) -> Machine {
+            let mut cpu = Cpu::new();
+            cpu.set_quirks(quirks);
+            cpu.set_seed(0);
+
+            Machine {
+                cpu,
+                ram: Ram::new(),
+                display: Display::new()
+            }
+        }
+
+        fn preset(name: &str) -> Machine {
+            Machine::with_quirks(Quirks::preset(name).unwrap())
+        }
+
+        // Writes the opcode at the PC and runs it.
+        fn step(&mut self, opcode: u16) -> Result<(), CpuError> {
+            let pc = self.cpu.get_pc();
+            self.ram.poke(pc, (opcode >> 8) as u8);
+            self.ram.poke(pc.wrapping_add(1), opcode as u8);
+
+            self.cpu.run_instruction(&mut self.ram, &mut self.display)
+        }
+
+        fn run(&mut self, opcode: u16) {
+            self.step(opcode).unwrap();
+        }
+
+        // Writes the program at 0x200 and runs that many instructions from there.
+        fn run_program(&mut self, program: &[u16], count: usize) {
+            for (i, opcode) in program.iter().enumerate() {
+                let addr = 0x200 + 2 * i as u16;
+                self.ram.poke(addr, (opcode >> 8) as u8);
+                self.ram.poke(addr + 1, *opcode as u8);
+            }
+
+            for _ in 0..count {
+                self.cpu.run_instruction(&mut self.ram, &mut self.display).unwrap();
+            }
+        }
+
+        fn set(&mut self, reg_index: u8, value: u8) {
+            self.cpu.set_register(reg_index, value);
+        }
+
+        fn v(&self, reg_index: u8) -> u8 {
+            self.cpu.get_register(reg_index)
         }
+
+        fn pc(&self) -> u16 {
+            self.cpu.get_pc()
+        }
+
+        // VX and VF after the 8XYN opcode, with VX and VY set first.
+        fn alu(&mut self, opcode: u16, vx: u8, vy: u8) -> (u8, u8) {
+            let x = ((opcode >> 8) & 0xF) as u8;
+            let y = ((opcode >> 4) & 0xF) as u8;
+            self.set(0xF, 0xAA);
+            self.set(x, vx);
+            self.set(y, vy);
+            self.run(opcode);
+
+            (self.v(x), self.v(0xF))
+        }
+    }
+
+    #[test]
+    fn cls_clears_the_screen() {
+        let mut machine = Machine::new();
+        machine.display.set_pixel_state_at(3, 4, true);
+        machine.cpu.reset_draw_flag();
+
+        machine.run(0x00E0);
+
+        assert!(!machine.display.pixel_is_on_at(3, 4));
+        assert!(machine.cpu.get_draw_flag());
+        assert_eq!(machine.pc(), 0x202);
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let mut machine = Machine::new();
+
+        machine.run(0x2400);
+        assert_eq!(machine.pc(), 0x400);
+        assert_eq!(machine.cpu.get_sp(), 1);
+        assert_eq!(machine.cpu.get_stack()[0], 0x200);
+
+        machine.run(0x00EE);
+        assert_eq!(machine.pc(), 0x202);
+        assert_eq!(machine.cpu.get_sp(), 0);
+    }
+
+    #[test]
+    fn call_with_a_full_stack_overflows() {
+        let mut machine = Machine::new();
+        for _ in 0..STACK_SIZE {
+            machine.run(0x2200);
+        }
+
+        assert_eq!(machine.step(0x2200), Err(CpuError::StackOverflow));
+        assert_eq!(machine.cpu.get_sp(), STACK_SIZE as u16);
+        assert_eq!(machine.pc(), 0x200);
+    }
+
+    #[test]
+    fn ret_with_an_empty_stack_underflows() {
+        let mut machine = Machine::new();
+
+        assert_eq!(machine.step(0x00EE), Err(CpuError::StackUnderflow));
+        assert_eq!(machine.cpu.get_sp(), 0);
+        assert_eq!(machine.pc(), 0x200);
+    }
+
+    #[test]
+    fn sys_and_invalid_opcodes_are_errors() {
+        let mut machine = Machine::new();
+
+        for opcode in [0x0000, 0x0123, 0x5121, 0x912F, 0xE000, 0xF0FF] {
+            assert_eq!(machine.step(opcode), Err(CpuError::UnknownOpcode(opcode)));
+            assert_eq!(machine.pc(), 0x200);
+        }
+    }
+
+    #[test]
+    fn jp() {
+        let mut machine = Machine::new();
+        machine.run(0x1ABC);
+
+        assert_eq!(machine.pc(), 0xABC);
+    }
+
+    #[test]
+    fn skips() {
+        // (opcode, VX, VY, skipped)
+        let cases = [
+            (0x3142, 0x42, 0x00, true),
+            (0x3142, 0x41, 0x00, false),
+            (0x4142, 0x42, 0x00, false),
+            (0x4142, 0x41, 0x00, true),
+            (0x5120, 0x07, 0x07, true),
+            (0x5120, 0x07, 0x08, false),
+            (0x9120, 0x07, 0x07, false),
+            (0x9120, 0x07, 0x08, true)
+        ];
+
+        for (opcode, vx, vy, skipped) in cases {
+            let mut machine = Machine::new();
+            machine.set(1, vx);
+            machine.set(2, vy);
+            machine.run(opcode);
+
+            assert_eq!(machine.pc(), if skipped {0x204} else {0x202}, "{:04X} with {:02X}, {:02X}", opcode, vx, vy);
+        }
+    }
+
+    #[test]
+    fn ld_and_add_immediate() {
+        let mut machine = Machine::new();
+        machine.run(0x6A12);
+        assert_eq!(machine.v(0xA), 0x12);
+
+        machine.run(0x7A34);
+        assert_eq!(machine.v(0xA), 0x46);
+    }
+
+    #[test]
+    fn add_immediate_wraps_without_flag() {
+        let mut machine = Machine::new();
+        machine.set(0xF, 0x0);
+        machine.set(0x3, 0xFF);
+        machine.run(0x7302);
+
+        assert_eq!(machine.v(0x3), 0x01);
+        assert_eq!(machine.v(0xF), 0x0);
+    }
+
+    #[test]
+    fn ld_register() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8120, 0x11, 0x22), (0x22, 0xAA));
+    }
+
+    #[test]
+    fn logic_operations() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8121, 0x0C, 0x0A), (0x0E, 0xAA));
+        assert_eq!(machine.alu(0x8122, 0x0C, 0x0A), (0x08, 0xAA));
+        assert_eq!(machine.alu(0x8123, 0x0C, 0x0A), (0x06, 0xAA));
+    }
+
+    #[test]
+    fn logic_operations_reset_vf_with_the_quirk() {
+        let mut machine = Machine::preset("chip8");
+        assert_eq!(machine.alu(0x8121, 0x0C, 0x0A), (0x0E, 0x00));
+        assert_eq!(machine.alu(0x8122, 0x0C, 0x0A), (0x08, 0x00));
+        assert_eq!(machine.alu(0x8123, 0x0C, 0x0A), (0x06, 0x00));
+    }
+
+    #[test]
+    fn add_sets_the_carry() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8124, 0x10, 0x20), (0x30, 0));
+        assert_eq!(machine.alu(0x8124, 0xFF, 0x00), (0xFF, 0));
+        assert_eq!(machine.alu(0x8124, 0xFF, 0x01), (0x00, 1));
+        assert_eq!(machine.alu(0x8124, 0xFF, 0xFF), (0xFE, 1));
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8125, 0x30, 0x10), (0x20, 1));
+        assert_eq!(machine.alu(0x8125, 0x10, 0x10), (0x00, 1));
+        assert_eq!(machine.alu(0x8125, 0x10, 0x30), (0xE0, 0));
+        assert_eq!(machine.alu(0x8125, 0x00, 0xFF), (0x01, 0));
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8127, 0x10, 0x30), (0x20, 1));
+        assert_eq!(machine.alu(0x8127, 0x10, 0x10), (0x00, 1));
+        assert_eq!(machine.alu(0x8127, 0x30, 0x10), (0xE0, 0));
+    }
+
+    #[test]
+    fn shifts_in_place_with_the_quirk() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8126, 0x05, 0xF0), (0x02, 1));
+        assert_eq!(machine.alu(0x8126, 0x04, 0xF1), (0x02, 0));
+        assert_eq!(machine.alu(0x812E, 0x81, 0x0F), (0x02, 1));
+        assert_eq!(machine.alu(0x812E, 0x41, 0x8F), (0x82, 0));
+    }
+
+    #[test]
+    fn shifts_vy_without_the_quirk() {
+        let mut machine = Machine::preset("chip8");
+        assert_eq!(machine.alu(0x8126, 0xF0, 0x05), (0x02, 1));
+        assert_eq!(machine.alu(0x8126, 0xF1, 0x04), (0x02, 0));
+        assert_eq!(machine.alu(0x812E, 0x0F, 0x81), (0x02, 1));
+        assert_eq!(machine.alu(0x812E, 0x8F, 0x41), (0x82, 0));
+    }
+
+    #[test]
+    fn vf_as_operand_keeps_the_flag_without_the_vf_order_quirk() {
+        let mut machine = Machine::preset("xochip");
+        assert_eq!(machine.alu(0x8F14, 0xFF, 0x02), (0x01, 0x01));
+        assert_eq!(machine.alu(0x8F15, 0x01, 0x02), (0x00, 0x00));
+        assert_eq!(machine.alu(0x8F16, 0x00, 0x03), (0x01, 0x01));
+        assert_eq!(machine.alu(0x8F1E, 0x00, 0x40), (0x00, 0x00));
+    }
+
+    #[test]
+    fn vf_as_operand_keeps_the_result_with_the_vf_order_quirk() {
+        let mut machine = Machine::new();
+        assert_eq!(machine.alu(0x8F14, 0xFF, 0x02), (0x01, 0x01));
+        assert_eq!(machine.alu(0x8F15, 0x01, 0x02), (0xFF, 0xFF));
+        assert_eq!(machine.alu(0x8F16, 0x03, 0x00), (0x01, 0x01));
+        assert_eq!(machine.alu(0x8F1E, 0x40, 0x00), (0x80, 0x80));
+    }
+
+    #[test]
+    fn ld_i() {
+        let mut machine = Machine::new();
+        machine.run(0xA123);
+
+        assert_eq!(machine.cpu.get_index_reg(), 0x123);
+    }
+
+    #[test]
+    fn jp_v0() {
+        let mut machine = Machine::new();
+        machine.set(0x0, 0x10);
+        machine.set(0x3, 0x20);
+        machine.run(0xB345);
+
+        assert_eq!(machine.pc(), 0x355);
+    }
+
+    #[test]
+    fn jp_vx_with_the_quirk() {
+        let mut machine = Machine::preset("schip");
+        machine.set(0x0, 0x10);
+        machine.set(0x3, 0x20);
+        machine.run(0xB345);
+
+        assert_eq!(machine.pc(), 0x365);
+    }
+
+    #[test]
+    fn rnd_is_masked_and_seeded() {
+        let mut machine = Machine::new();
+        machine.run(0xC100);
+        assert_eq!(machine.v(1), 0x00);
+
+        let mut values = Vec::new();
+        for _ in 0..256 {
+            machine.run(0xC20F);
+            assert!(machine.v(2) <= 0x0F);
+            values.push(machine.v(2));
+        }
+        assert!(values.iter().any(|value| *value != values[0]));
+
+        let mut other = Machine::new();
+        other.run(0xC100);
+        for value in values {
+            other.run(0xC20F);
+            assert_eq!(other.v(2), value);
+        }
+    }
+
+    #[test]
+    fn rnd_reaches_every_byte() {
+        let mut machine = Machine::new();
+        let mut seen = [false; 256];
+        for _ in 0..4096 {
+            machine.cpu.set_pc(0x200);
+            machine.run(0xC1FF);
+            seen[machine.v(1) as usize] = true;
+        }
+
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn drw_draws_and_detects_collisions() {
+        let mut machine = Machine::new();
+        machine.ram.poke(0x300, 0b1100_0000);
+        machine.ram.poke(0x301, 0b0100_0000);
+        machine.cpu.set_index_reg(0x300);
+        machine.set(0x0, 10);
+        machine.set(0x1, 5);
+
+        machine.run(0xD012);
+        assert!(machine.display.pixel_is_on_at(10, 5));
+        assert!(machine.display.pixel_is_on_at(11, 5));
+        assert!(!machine.display.pixel_is_on_at(10, 6));
+        assert!(machine.display.pixel_is_on_at(11, 6));
+        assert_eq!(machine.v(0xF), 0);
+        assert!(machine.cpu.get_draw_flag());
+
+        machine.run(0xD011);
+        assert!(!machine.display.pixel_is_on_at(10, 5));
+        assert!(!machine.display.pixel_is_on_at(11, 5));
+        assert!(machine.display.pixel_is_on_at(11, 6));
+        assert_eq!(machine.v(0xF), 1);
+    }
+
+    #[test]
+    fn drw_wraps_around_the_edges() {
+        let mut machine = Machine::new();
+        machine.ram.poke(0x300, 0xFF);
+        machine.ram.poke(0x301, 0xFF);
+        machine.cpu.set_index_reg(0x300);
+        machine.set(0x0, 60);
+        machine.set(0x1, 31);
+        machine.run(0xD012);
+
+        assert!(machine.display.pixel_is_on_at(63, 31));
+        assert!(machine.display.pixel_is_on_at(0, 31));
+        assert!(machine.display.pixel_is_on_at(3, 0));
+        assert!(!machine.display.pixel_is_on_at(4, 0));
+    }
+
+    #[test]
+    fn drw_clips_at_the_edges_with_the_quirk() {
+        let mut machine = Machine::preset("schip");
+        machine.ram.poke(0x300, 0xFF);
+        machine.ram.poke(0x301, 0xFF);
+        machine.cpu.set_index_reg(0x300);
+        machine.set(0x0, 60);
+        machine.set(0x1, 31);
+        machine.run(0xD012);
+
+        assert!(machine.display.pixel_is_on_at(63, 31));
+        assert!(!machine.display.pixel_is_on_at(0, 31));
+        assert!(!machine.display.pixel_is_on_at(60, 0));
+
+        // the origin itself wraps.
+        machine.display.clear();
+        machine.set(0x0, 64 + 2);
+        machine.set(0x1, 32 + 1);
+        machine.run(0xD011);
+        assert!(machine.display.pixel_is_on_at(2, 1));
+        assert!(machine.display.pixel_is_on_at(9, 1));
+    }
+
+    #[test]
+    fn drw_waits_for_the_frame_with_the_quirk() {
+        let mut machine = Machine::preset("chip8");
+        machine.cpu.set_index_reg(0x300);
+        machine.ram.poke(0x300, 0x80);
+
+        machine.run(0xD011);
+        assert_eq!(machine.pc(), 0x200);
+        assert!(!machine.display.pixel_is_on_at(0, 0));
+
+        machine.cpu.update_timers();
+        machine.run(0xD011);
+        assert_eq!(machine.pc(), 0x202);
+        assert!(machine.display.pixel_is_on_at(0, 0));
+
+        machine.run(0xD011);
+        assert_eq!(machine.pc(), 0x202);
+    }
+
+    #[test]
+    fn key_skips() {
+        let mut machine = Machine::new();
+        machine.set(0x1, 0x0A);
+        machine.run(0xE19E);
+        assert_eq!(machine.pc(), 0x202);
+        machine.run(0xE1A1);
+        assert_eq!(machine.pc(), 0x206);
+
+        machine.cpu.key(0xA, true);
+        machine.run(0xE19E);
+        assert_eq!(machine.pc(), 0x20A);
+        machine.run(0xE1A1);
+        assert_eq!(machine.pc(), 0x20C);
+
+        // only the low nibble of VX selects the key.
+        machine.set(0x1, 0xFA);
+        machine.run(0xE19E);
+        assert_eq!(machine.pc(), 0x210);
+    }
+
+    #[test]
+    fn ld_vx_k_waits_for_a_key() {
+        let mut machine = Machine::new();
+        machine.run(0xF30A);
+        assert_eq!(machine.pc(), 0x200);
+
+        machine.cpu.key(0x7, true);
+        machine.run(0xF30A);
+        assert_eq!(machine.pc(), 0x202);
+        assert_eq!(machine.v(0x3), 0x7);
+    }
+
+    #[test]
+    fn timers() {
+        let mut machine = Machine::new();
+        machine.set(0x1, 0x02);
+        machine.run(0xF115);
+        machine.run(0xF118);
+        assert_eq!(machine.cpu.get_delay_timer(), 0x02);
+        assert_eq!(machine.cpu.get_sound_timer(), 0x02);
+
+        machine.cpu.update_timers();
+        machine.run(0xF207);
+        assert_eq!(machine.v(0x2), 0x01);
+
+        machine.cpu.update_timers();
+        machine.cpu.update_timers();
+        assert_eq!(machine.cpu.get_delay_timer(), 0x00);
+        assert_eq!(machine.cpu.get_sound_timer(), 0x00);
+    }
+
+    #[test]
+    fn add_i_wraps_without_flag() {
+        let mut machine = Machine::new();
+        machine.set(0xF, 0x0);
+        machine.cpu.set_index_reg(0x0FFF);
+        machine.set(0x1, 0x01);
+        machine.run(0xF11E);
+        assert_eq!(machine.cpu.get_index_reg(), 0x1000);
+        assert_eq!(machine.v(0xF), 0x0);
+
+        machine.cpu.set_index_reg(0xFFFF);
+        machine.run(0xF11E);
+        assert_eq!(machine.cpu.get_index_reg(), 0x0000);
+    }
+
+    #[test]
+    fn ld_f_points_to_the_glyph() {
+        let mut machine = Machine::new();
+        machine.cpu.set_font_address(0x50);
+        machine.set(0x1, 0x0A);
+        machine.run(0xF129);
+        assert_eq!(machine.cpu.get_index_reg(), 0x50 + 0xA * 5);
+
+        machine.set(0x1, 0xFA);
+        machine.run(0xF129);
+        assert_eq!(machine.cpu.get_index_reg(), 0x50 + 0xA * 5);
+    }
+
+    #[test]
+    fn ld_hf_points_to_the_big_digit() {
+        let mut machine = Machine::new();
+        machine.cpu.set_font_address(0x50);
+        assert_eq!(machine.step(0xF130), Err(CpuError::UnknownOpcode(0xF130)));
+
+        machine.cpu.set_instruction_set(InstructionSet::Schip);
+        machine.set(0x1, 0x07);
+        machine.run(0xF130);
+        assert_eq!(machine.cpu.get_index_reg(), 0x50 + 80 + 7 * 10);
+
+        machine.set(0x1, 0xF3);
+        machine.run(0xF130);
+        assert_eq!(machine.cpu.get_index_reg(), 0x50 + 80 + 3 * 10);
+    }
+
+    #[test]
+    fn cached_instructions_follow_the_instruction_set() {
+        let mut machine = Machine::new();
+        machine.cpu.set_instruction_set(InstructionSet::Schip);
+        machine.run(0xF030);
+
+        // the same bytes, cached as LD HF, V0.
+        machine.cpu.set_pc(PC_START);
+        machine.cpu.set_instruction_set(InstructionSet::Chip8);
+        assert_eq!(machine.cpu.run_instruction(&mut machine.ram, &mut machine.display), Err(CpuError::UnknownOpcode(0xF030)));
+    }
+
+    #[test]
+    fn ld_b_stores_the_decimal_digits() {
+        let mut machine = Machine::new();
+        machine.cpu.set_index_reg(0x300);
+
+        for (value, digits) in [(255, [2, 5, 5]), (109, [1, 0, 9]), (7, [0, 0, 7])] {
+            machine.set(0x1, value);
+            machine.run(0xF133);
+            assert_eq!([machine.ram.peek(0x300), machine.ram.peek(0x301), machine.ram.peek(0x302)], digits);
+            assert_eq!(machine.cpu.get_index_reg(), 0x300);
+        }
+    }
+
+    #[test]
+    fn store_and_load_registers_keep_i_with_the_quirk() {
+        let mut machine = Machine::new();
+        machine.cpu.set_index_reg(0x300);
+        for i in 0..4 {
+            machine.set(i, 0x10 + i);
+        }
+
+        machine.run(0xF255);
+        assert_eq!([machine.ram.peek(0x300), machine.ram.peek(0x301), machine.ram.peek(0x302), machine.ram.peek(0x303)], [0x10, 0x11, 0x12, 0x00]);
+        assert_eq!(machine.cpu.get_index_reg(), 0x300);
+
+        machine.ram.poke(0x300, 0x55);
+        machine.run(0xF165);
+        assert_eq!([machine.v(0), machine.v(1), machine.v(2)], [0x55, 0x11, 0x12]);
+        assert_eq!(machine.cpu.get_index_reg(), 0x300);
+    }
+
+    #[test]
+    fn store_and_load_registers_increment_i_without_the_quirk() {
+        let mut machine = Machine::preset("chip8");
+        machine.cpu.set_index_reg(0x300);
+
+        machine.run(0xF255);
+        assert_eq!(machine.cpu.get_index_reg(), 0x303);
+
+        machine.run(0xF065);
+        assert_eq!(machine.cpu.get_index_reg(), 0x304);
+    }
+
+    #[test]
+    fn pc_wraps_at_the_end_of_memory() {
+        let mut machine = Machine::new();
+        machine.ram.set_memory_map(MemoryMap { size: XO_MEMORY_SIZE, ..MemoryMap::default() });
+        machine.cpu.set_pc(0xFFFE);
+        machine.run(0x6001);
+
+        assert_eq!(machine.pc(), 0x0000);
+    }
+
+    #[test]
+    fn set_sp_stays_within_the_stack() {
+        let mut machine = Machine::new();
+        machine.cpu.set_sp(0xFFFF);
+
+        assert_eq!(machine.cpu.get_sp(), STACK_SIZE as u16);
+        assert_eq!(machine.step(0x2200), Err(CpuError::StackOverflow));
+    }
+
+    #[test]
+    fn self_modifying_code_runs_the_new_instruction() {
+        // the loop runs ADD V2, 1 twice, then the program writes F3 55 over it
+        let mut machine = Machine::new();
+        machine.run_program(&[
+            0x7201, // 200 : ADD V2, 1
+            0x3202, // 202 : SE V2, 2
+            0x1200, // 204 : JP 200
+            0x60F3, // 206 : LD V0, F3
+            0x6155, // 208 : LD V1, 55
+            0xA200, // 20A : LD I, 200
+            0xF155, // 20C : LD [I], V1
+            0x1200  // 20E : JP 200
+        ], 11);
+
+        // LD [I], V3 ran instead of the cached ADD V2, 1
+        assert_eq!(machine.v(2), 2);
+        assert_eq!(machine.ram.peek(0x202), 2);
+        assert_eq!(machine.ram.peek(0x203), 0);
+        assert_eq!(machine.pc(), 0x202);
+    }
+
+    #[test]
+    fn self_modifying_code_sees_the_second_byte() {
+        // only the byte of ADD V2, 1 is rewritten
+        let mut machine = Machine::new();
+        machine.run_program(&[
+            0x7201, // 200 : ADD V2, 1
+            0x3202, // 202 : SE V2, 2
+            0x1200, // 204 : JP 200
+            0x6005, // 206 : LD V0, 5
+            0xA201, // 208 : LD I, 201
+            0xF055, // 20A : LD [I], V0
+            0x1200  // 20C : JP 200
+        ], 10);
+
+        assert_eq!(machine.v(2), 7);
+        assert_eq!(machine.pc(), 0x202);
     }
 }
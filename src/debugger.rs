@@ -0,0 +1,128 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use tlmc::disassembler::disassemble;
+use tlmc::emulator::Emulator;
+use crate::text::{draw_text, LINE_HEIGHT};
+
+pub const PANEL_WIDTH: u32 = 480;
+
+const PANEL_MARGIN: i32 = 8;
+
+const DISASM_LINES: u16 = 7;
+const MEMORY_LINES: u16 = 4;
+const MEMORY_ROW_SIZE: u16 = 8;
+
+const PANEL_COLOR: Color = Color::RGB(24, 24, 32);
+const TEXT_COLOR: Color = Color::RGB(200, 200, 200);
+const HIGHLIGHT_COLOR: Color = Color::RGB(255, 200, 0);
+
+pub struct Debugger {
+    visible: bool,
+    paused: bool,
+    memory_offset: u16
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            visible: false,
+            paused: false,
+            memory_offset: 0x200
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_visible(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn scroll_memory(&mut self, rows: i32, memory_size: usize) {
+        let offset = self.memory_offset as i32 + rows * MEMORY_ROW_SIZE as i32;
+        let max_offset = memory_size as i32 - (MEMORY_LINES * MEMORY_ROW_SIZE) as i32;
+
+        self.memory_offset = offset.clamp(0, max_offset) as u16;
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, emulator: &Emulator, origin_x: i32) {
+        canvas.set_draw_color(PANEL_COLOR);
+        let (_, height) = canvas.window().size();
+        canvas.fill_rect(Rect::new(origin_x, 0, PANEL_WIDTH, height)).unwrap();
+
+        let mut lines: Vec<(String, Color)> = Vec::new();
+
+        let state = if self.paused {"PAUSED"} else {"RUNNING"};
+        lines.push((format!("PC {:04X}  I {:04X}  SP {:X}  {}", emulator.get_pc(), emulator.get_index_reg(), emulator.get_sp(), state), TEXT_COLOR));
+        lines.push((format!("DT {:02X}  ST {:02X}", emulator.get_delay_timer(), emulator.get_sound_timer()), TEXT_COLOR));
+        lines.push((String::new(), TEXT_COLOR));
+
+        for row in 0..4 {
+            let mut line = String::new();
+            for col in 0..4 {
+                let reg_index = row * 4 + col;
+                line.push_str(&format!("V{:X} {:02X}  ", reg_index, emulator.get_register(reg_index)));
+            }
+            lines.push((line, TEXT_COLOR));
+        }
+        lines.push((String::new(), TEXT_COLOR));
+
+        let stack = emulator.get_stack();
+        for half in stack.chunks(stack.len() / 2) {
+            let line: Vec<String> = half.iter().map(|addr| format!("{:04X}", addr)).collect();
+            lines.push((format!("STACK {}", line.join(" ")), TEXT_COLOR));
+        }
+
+        let keys: String = (0..16u8)
+            .map(|n| if emulator.key_is_down(n) {format!("{:X}", n)} else {".".to_string()})
+            .collect();
+        lines.push((format!("KEYS  {}", keys), TEXT_COLOR));
+        lines.push((String::new(), TEXT_COLOR));
+
+        let pc = emulator.get_pc();
+        let memory_size = emulator.memory_size();
+        for i in 0..DISASM_LINES {
+            let addr = (pc.wrapping_sub((DISASM_LINES / 2) * 2).wrapping_add(i * 2) as usize % memory_size) as u16;
+            let opcode = (emulator.read_memory(addr) as u16) << 8 | emulator.read_memory(((addr as usize + 1) % memory_size) as u16) as u16;
+
+            if addr == pc {
+                lines.push((format!("> {:04X}: {:04X} {}", addr, opcode, disassemble(opcode)), HIGHLIGHT_COLOR));
+            } else {
+                lines.push((format!("  {:04X}: {:04X} {}", addr, opcode, disassemble(opcode)), TEXT_COLOR));
+            }
+        }
+        lines.push((String::new(), TEXT_COLOR));
+
+        for row in 0..MEMORY_LINES {
+            let addr = self.memory_offset + row * MEMORY_ROW_SIZE;
+            let mut line = format!("{:04X}:", addr);
+            for i in 0..MEMORY_ROW_SIZE {
+                line.push_str(&format!(" {:02X}", emulator.read_memory(addr + i)));
+            }
+            lines.push((line, TEXT_COLOR));
+        }
+        lines.push(("F1 PANEL  F5 RUN/PAUSE  F6 STEP  F7 FRAME".to_string(), TEXT_COLOR));
+
+        for (i, (line, color)) in lines.iter().enumerate() {
+            draw_text(canvas, line, origin_x + PANEL_MARGIN, PANEL_MARGIN + i as i32 * LINE_HEIGHT, *color);
+        }
+    }
+}
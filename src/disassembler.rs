@@ -0,0 +1,46 @@
+use crate::coverage::{Coverage, EXECUTED, SPRITE, DATA_READ, DATA_WRITE};
+use crate::instruction::{decode, InstructionSet};
+
+const ROM_START: u16 = 0x200;
+
+// Every instruction TLMC knows is listed, the SUPER-CHIP ones included.
+pub fn disassemble(opcode: u16) -> String {
+    match decode(opcode, InstructionSet::Schip) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => format!("DW 0x{:04X}", opcode)
+    }
+}
+
+pub fn opcode_family(opcode: u16) -> &'static str {
+    decode(opcode, InstructionSet::Schip).map_or("????", |instruction| instruction.family())
+}
+
+// Lists a ROM loaded at 0x200. With a coverage map, only the executed bytes are
+// decoded as instructions and everything else is listed as data.
+pub fn disassemble_rom(rom: &[u8], coverage: Option<&Coverage>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = ROM_START + offset as u16;
+        let flags = coverage.map_or(EXECUTED, |coverage| coverage.get_flags(addr));
+
+        if flags & EXECUTED != 0 && offset + 1 < rom.len() {
+            let opcode = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+            lines.push(format!("{:03X}: {:04X}  {}", addr, opcode, disassemble(opcode)));
+            offset += 2;
+        } else {
+            let usage = if flags & SPRITE != 0 {
+                "  ; sprite"
+            } else if flags & (DATA_READ | DATA_WRITE) != 0 {
+                "  ; data"
+            } else {
+                ""
+            };
+            lines.push(format!("{:03X}: {:02X}    DB 0x{:02X}{}", addr, rom[offset], rom[offset], usage));
+            offset += 1;
+        }
+    }
+
+    lines
+}
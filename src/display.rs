@@ -1,11 +1,20 @@
+use crate::save_state::{StateError, StateReader, StateWriter};
+
 const RENDER_TABLE_WIDTH: usize = 64;
 const RENDER_TABLE_HEIGHT: usize = 32;
 const RENDER_TABLE_SIZE: usize = RENDER_TABLE_WIDTH * RENDER_TABLE_HEIGHT;
 
+#[derive(Clone)]
 pub struct Display {
     render_table: [bool; RENDER_TABLE_SIZE]
 }
 
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
+
 impl Display {
     pub fn new() -> Display {
         Display {
@@ -21,10 +30,26 @@ impl Display {
         self.render_table[y as usize * RENDER_TABLE_WIDTH + x as usize] = new_state;
     }
 
+    // One bit per pixel.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for pixels in self.render_table.chunks(8) {
+            writer.u8(pixels.iter().fold(0x0, |byte, pixel| byte << 1 | *pixel as u8));
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let bytes = reader.bytes(RENDER_TABLE_SIZE / 8)?;
+        for (i, pixel) in self.render_table.iter_mut().enumerate() {
+            *pixel = bytes[i / 8] & (0x80 >> (i % 8)) != 0;
+        }
+
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
         for y in 0..RENDER_TABLE_HEIGHT {
             for x in 0..RENDER_TABLE_WIDTH {
-                self.render_table[y as usize * RENDER_TABLE_WIDTH + x as usize] = false;
+                self.render_table[y * RENDER_TABLE_WIDTH + x] = false;
             }
         }
     }
@@ -1,46 +1,159 @@
-use std::io::{Read, BufReader};
-use std::fs::File;
+use std::fmt;
+use std::io::{self, Read};
 
-use crate::cpu::Cpu;
-use crate::ram::Ram;
+use crate::cpu::{Cpu, CpuError};
+use crate::ram::{Ram, MemoryMap, WatchKind, WatchHit, AccessRecord, XO_MEMORY_SIZE};
 use crate::display::Display;
+use crate::tracer::Tracer;
+use crate::profiler::Profiler;
+use crate::coverage::Coverage;
+use crate::quirks::Quirks;
+use crate::instruction::InstructionSet;
+use crate::font::{FontStyle, FONT_SIZE, BIG_FONT_SIZE, MAX_FONT_ADDRESS, SCHIP_BIG_FONT};
+use crate::rom_database::rom_hash;
+use crate::rom_loader::{read_all, read_rom};
+use crate::assembler::AsmError;
+use crate::save_state::{self, StateError, StateReader, StateWriter};
 
-static FONTSET: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
-
-pub struct Emulator<'a> {
-    cpu: Cpu<'a>,
+const ROM_START: u16 = 0x200;
+const MAX_ROM_SIZE: usize = XO_MEMORY_SIZE - ROM_START as usize;
+
+#[derive(Debug)]
+pub enum RomError {
+    Open(io::Error),
+    Read(io::Error),
+    Archive(zip::result::ZipError),
+    NoRomInArchive,
+    MultipleRomsInArchive(Vec<String>),
+    Cartridge(String),
+    Source(AsmError),
+    Empty,
+    TooLarge(usize)
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Open(why) => write!(f, "Unable to open the ROM file ({})", why),
+            RomError::Read(why) => write!(f, "Unable to read the ROM file ({})", why),
+            RomError::Archive(why) => write!(f, "Unable to read the ROM archive ({})", why),
+            RomError::NoRomInArchive => write!(f, "The archive does not hold any ROM"),
+            RomError::MultipleRomsInArchive(entries) => write!(f, "The archive holds {} ROMs", entries.len()),
+            RomError::Cartridge(why) => write!(f, "Unable to decode the Octo cartridge ({})", why),
+            RomError::Source(why) => write!(f, "Unable to compile the Octo program ({})", why),
+            RomError::Empty => write!(f, "The ROM file is empty"),
+            RomError::TooLarge(size) => write!(f, "The ROM is {} bytes long, but at most {} bytes fit into memory", size, MAX_ROM_SIZE)
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+pub struct Emulator {
+    cpu: Cpu,
     ram: Ram,
-    display: Display
+    display: Display,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    rom: Vec<u8>,
+    rom_hash: Option<String>,
+    font: FontStyle,
+    font_address: u16
 }
 
-impl <'a>  Emulator<'a> {
-    pub fn new() -> Emulator<'a> {
+// A clone runs on its own from the same state. The tracer writes to a file, so it stays
+// with the original.
+impl Clone for Emulator {
+    fn clone(&self) -> Emulator {
+        Emulator {
+            cpu: self.cpu.clone(),
+            ram: self.ram.clone(),
+            display: self.display.clone(),
+            tracer: None,
+            profiler: self.profiler.clone(),
+            coverage: self.coverage.clone(),
+            rom: self.rom.clone(),
+            rom_hash: self.rom_hash.clone(),
+            font: self.font,
+            font_address: self.font_address
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Emulator {
+        Emulator::new()
+    }
+}
+
+impl Emulator {
+    pub fn new() -> Emulator {
         Emulator {
             cpu: Cpu::new(),
             ram: Ram::new(),
-            display: Display::new()
+            display: Display::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            rom: Vec::new(),
+            rom_hash: None,
+            font: FontStyle::default(),
+            font_address: 0x0
+        }
+    }
+
+    // On error, the emulator is left as it was before the instruction.
+    pub fn run_instruction(&mut self) -> Result<(), CpuError> {
+        let pc = self.cpu.get_pc();
+        self.ram.set_access_pc(pc);
+
+        // Only the tools need the opcode, the CPU gets its instructions from the cache.
+        if self.tracer.is_none() && self.profiler.is_none() && self.coverage.is_none() {
+            return self.cpu.run_instruction(&mut self.ram, &mut self.display);
+        }
+
+        let opcode = (self.ram.peek(pc) as u16) << 8 | self.ram.peek(pc.wrapping_add(1)) as u16;
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, opcode);
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(pc, opcode, &self.cpu);
         }
+
+        self.cpu.run_instruction(&mut self.ram, &mut self.display)?;
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            if let Err(why) = tracer.record(pc, opcode, &self.cpu) {
+                println!("Error : Unable to write the trace, tracing stopped !");
+                println!("Why -> {:?}", why);
+                self.tracer = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn get_profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.ram.size()));
     }
 
-    pub fn run_instruction(&mut self) {
-        self.cpu.run_instruction(&mut self.ram, &mut self.display);
+    pub fn get_coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
     }
 
     pub fn reset_draw_flag(&mut self) {
@@ -55,42 +168,222 @@ impl <'a>  Emulator<'a> {
         self.cpu.update_timers();
     }
 
-    pub fn load_rom(&mut self, rom_path: &str) {
-        // load FONTSET into memory.
-        for i in 0..FONTSET.len() {
-            self.ram.write(i as u16, FONTSET[i]);
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), RomError> {
+        let rom = read_rom(rom_path)?;
+
+        self.load_rom_from_bytes(&rom)
+    }
+
+    pub fn load_rom_from_reader<R: Read>(&mut self, reader: R) -> Result<(), RomError> {
+        let rom = read_all(reader)?;
+
+        self.load_rom_from_bytes(&rom)
+    }
+
+    pub fn load_rom_from_bytes(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        if rom.is_empty() {
+            return Err(RomError::Empty);
         }
 
-        let mut rom_buffer = Vec::new();
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(RomError::TooLarge(rom.len()));
+        }
 
-        match File::open(rom_path) {
-            Ok(rom_file) => {
-                let mut reader = BufReader::new(rom_file);
+        self.rom = rom.to_vec();
+        self.rom_hash = Some(rom_hash(rom));
+        self.fit_memory_to_rom();
 
-                match reader.read_to_end(&mut rom_buffer) {
-                    Ok(_) => (),
-                    Err(why) => {
-                        println!("Error : Unable to read the given file !");
-                        println!("Why -> {:?}", why);
-                    }
-                }
-            },
-            Err(why) => {
-                println!("Error : Unable to open the given file !");
-                println!("Why -> {:?}", why);
-            }
+        // the profile and the coverage map of the previous ROM are meaningless now.
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new(self.ram.size()));
+        }
+
+        self.reset();
+
+        Ok(())
+    }
+
+    // Replaces the ROM of a running program. When the new ROM has the same size and the same
+    // instructions at the PC and at the return addresses on the stack, only the ROM bytes are
+    // replaced and the registers and the rest of the memory are kept. Otherwise the new ROM
+    // is restarted. Returns whether the state was kept.
+    pub fn reload_rom(&mut self, rom: &[u8], keep_state: bool) -> Result<bool, RomError> {
+        if !keep_state || !self.same_layout(rom) {
+            self.load_rom_from_bytes(rom)?;
+            return Ok(false);
+        }
+
+        for (i, byte) in rom.iter().enumerate() {
+            self.ram.poke(ROM_START + i as u16, *byte);
+        }
+
+        self.rom = rom.to_vec();
+        self.rom_hash = Some(rom_hash(rom));
+
+        Ok(true)
+    }
+
+    fn same_layout(&self, rom: &[u8]) -> bool {
+        if rom.len() != self.rom.len() {
+            return false;
         }
 
+        let sp = (self.cpu.get_sp() as usize).min(self.cpu.get_stack().len());
+        let mut addresses = vec![self.cpu.get_pc()];
+        addresses.extend_from_slice(&self.cpu.get_stack()[..sp]);
+
+        addresses.iter().all(|addr| {
+            let offset = addr.wrapping_sub(ROM_START) as usize;
+            offset >= rom.len() || rom.get(offset..offset + 2) == self.rom.get(offset..offset + 2)
+        })
+    }
+
+    // Clears the CPU, the memory, the display and the timers, then restarts the loaded ROM.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ram.clear();
+        self.display.clear();
+
+        self.load_font();
+
         // load rom content into the memory.
-        if rom_buffer.len() <= 0xFFF - 0x200 {
-            for i in 0..rom_buffer.len() {
-                self.ram.write(i as u16 + 0x200, rom_buffer[i]);
+        for (i, byte) in self.rom.iter().enumerate() {
+            self.ram.poke(ROM_START + i as u16, *byte);
+        }
+    }
+
+    // The ROM, the font and the machine : loading the state on another emulator, with any
+    // ROM loaded, gives the same program at the same point.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.block(&self.rom);
+        writer.block(self.font.name().as_bytes());
+        writer.u16(self.font_address);
+        self.cpu.save_state(&mut writer);
+        self.ram.save_state(&mut writer);
+        self.display.save_state(&mut writer);
+
+        writer.finish()
+    }
+
+    // On error, the emulator is left as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state)?;
+        let rom = reader.block()?;
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(StateError::Invalid("ROM size"));
+        }
+        let font = std::str::from_utf8(reader.block()?).ok().and_then(FontStyle::from_name).ok_or(StateError::Invalid("font"))?;
+        let font_address = reader.u16()?;
+        if font_address > MAX_FONT_ADDRESS {
+            return Err(StateError::Invalid("font address"));
+        }
+
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&mut reader)?;
+        if cpu.get_font_address() != font_address {
+            return Err(StateError::Invalid("font address"));
+        }
+        let mut ram = self.ram.clone();
+        ram.load_state(&mut reader)?;
+        let mut display = self.display.clone();
+        display.load_state(&mut reader)?;
+        reader.finish()?;
+
+        if self.coverage.is_some() && ram.size() != self.ram.size() {
+            self.coverage = Some(Coverage::new(ram.size()));
+        }
+
+        self.cpu = cpu;
+        self.ram = ram;
+        self.display = display;
+        self.rom = rom.to_vec();
+        self.rom_hash = if rom.is_empty() {None} else {Some(rom_hash(rom))};
+        self.font = font;
+        self.font_address = font_address;
+
+        Ok(())
+    }
+
+    // Equal on two emulators that run the same way.
+    pub fn state_checksum(&self) -> u64 {
+        save_state::checksum(&self.save_state())
+    }
+
+    pub fn get_rom_hash(&self) -> Option<&str> {
+        self.rom_hash.as_deref()
+    }
+
+    // Replaces the font in memory, the program keeps running.
+    pub fn set_font(&mut self, font: FontStyle, address: u16) {
+        if font == self.font && address == self.font_address {
+            return;
+        }
+
+        for i in 0..(FONT_SIZE + BIG_FONT_SIZE) as u16 {
+            self.ram.poke(self.font_address.wrapping_add(i), 0x0);
+        }
+
+        self.font = font;
+        self.font_address = address;
+        self.cpu.set_font_address(address);
+        self.load_font();
+    }
+
+    // The 4x5 font, followed by the SUPER-CHIP 8x10 digits.
+    fn load_font(&mut self) {
+        let glyphs = self.font.glyphs().iter().chain(SCHIP_BIG_FONT.iter());
+        for (i, byte) in glyphs.enumerate() {
+            self.ram.poke(self.font_address.wrapping_add(i as u16), *byte);
+        }
+    }
+
+    // A new memory size restarts the program.
+    pub fn set_memory_map(&mut self, map: MemoryMap) {
+        let size = self.ram.size();
+        self.ram.set_memory_map(map);
+        self.fit_memory_to_rom();
+
+        if self.ram.size() != size {
+            if self.coverage.is_some() {
+                self.coverage = Some(Coverage::new(self.ram.size()));
             }
-        } else {
-            println!("Error : This ROM file is too large to fit into memory !");
+            self.reset();
         }
     }
 
+    // ROMs too large for 4 KiB get the 64 KiB memory of XO-CHIP.
+    fn fit_memory_to_rom(&mut self) {
+        if ROM_START as usize + self.rom.len() > self.ram.size() {
+            let map = MemoryMap { size: XO_MEMORY_SIZE, ..self.ram.get_memory_map() };
+            self.ram.set_memory_map(map);
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.ram.size()
+    }
+
+    // The cache is on by default, turning it off gives the plain interpreter.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.ram.set_instruction_cache(enabled);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.set_seed(seed);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.set_quirks(quirks);
+    }
+
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        self.cpu.set_instruction_set(instruction_set);
+    }
+
     pub fn pixel_is_on_at(&self, x: u8, y: u8) -> bool {
         self.display.pixel_is_on_at(x, y)
     }
@@ -98,4 +391,88 @@ impl <'a>  Emulator<'a> {
     pub fn key(&mut self, n: u8, is_down: bool) {
         self.cpu.key(n, is_down);
     }
+
+    pub fn key_is_down(&self, n: u8) -> bool {
+        self.cpu.key_is_down(n)
+    }
+
+    pub fn get_register(&self, reg_index: u8) -> u8 {
+        self.cpu.get_register(reg_index)
+    }
+
+    pub fn get_index_reg(&self) -> u16 {
+        self.cpu.get_index_reg()
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.cpu.get_pc()
+    }
+
+    pub fn get_sp(&self) -> u16 {
+        self.cpu.get_sp()
+    }
+
+    pub fn get_stack(&self) -> &[u16] {
+        self.cpu.get_stack()
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.cpu.get_delay_timer()
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.cpu.get_sound_timer()
+    }
+
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+
+    pub fn set_register(&mut self, reg_index: u8, reg_value: u8) {
+        self.cpu.set_register(reg_index, reg_value);
+    }
+
+    pub fn set_index_reg(&mut self, value: u16) {
+        self.cpu.set_index_reg(value);
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.cpu.set_pc(value);
+    }
+
+    pub fn set_sp(&mut self, value: u16) {
+        self.cpu.set_sp(value);
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.cpu.set_delay_timer(value);
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.cpu.set_sound_timer(value);
+    }
+
+    pub fn write_memory(&mut self, addr: u16, value: u8) {
+        self.ram.poke(addr, value);
+    }
+
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.ram.add_watchpoint(start, end, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.ram.remove_watchpoint(start, end, kind);
+    }
+
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.ram.take_watch_hit()
+    }
+
+    pub fn enable_access_log(&mut self) {
+        self.ram.enable_access_log();
+    }
+
+    pub fn get_access_log(&self) -> Option<&[AccessRecord]> {
+        self.ram.get_access_log()
+    }
 }
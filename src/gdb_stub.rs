@@ -0,0 +1,432 @@
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+
+use tlmc::emulator::Emulator;
+use tlmc::ram::{WatchKind, WatchHit};
+
+const REGISTERS_COUNT: usize = 16;
+
+// GDB signal numbers used in stop replies.
+const SIGINT: u8 = 0x02;
+const SIGILL: u8 = 0x04;
+const SIGTRAP: u8 = 0x05;
+const SIGSEGV: u8 = 0x0B;
+
+// Register numbers : V0-VF, then I, PC, SP, DT and ST.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REGS_TOTAL: usize = 21;
+
+pub struct GdbStub {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    breakpoints: Vec<u16>,
+    running: bool,
+    skip_breakpoint: bool
+}
+
+impl GdbStub {
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            stream,
+            buffer: Vec::new(),
+            breakpoints: Vec::new(),
+            running: false,
+            skip_breakpoint: false
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // Handles the pending packets and stops the target on breakpoints.
+    // Returns false once the client asked to kill the target.
+    pub fn poll(&mut self, emulator: &mut Emulator) -> io::Result<bool> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "GDB client disconnected")),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(ref why) if why.kind() == ErrorKind::WouldBlock => break,
+                Err(why) => return Err(why)
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            if !self.handle_packet(&packet, emulator)? {
+                return Ok(false);
+            }
+        }
+
+        if self.running {
+            if self.skip_breakpoint {
+                self.skip_breakpoint = false;
+            } else if self.breakpoints.contains(&emulator.get_pc()) {
+                self.running = false;
+                self.send_stop_reply(SIGTRAP)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                // Ctrl-C from the client interrupts a running target.
+                Some(0x03) => {
+                    self.buffer.remove(0);
+                    if self.running {
+                        self.running = false;
+                        self.send_stop_reply(SIGINT)?;
+                    }
+                },
+                Some(b'$') => break,
+                // Acks and line noise between packets.
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+
+        let end = match self.buffer.iter().position(|byte| *byte == b'#') {
+            Some(end) if self.buffer.len() >= end + 3 => end,
+            _ => return Ok(None)
+        };
+
+        let payload = self.buffer[1..end].to_vec();
+        let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        self.buffer.drain(..end + 3);
+
+        if checksum != Some(payload.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))) {
+            self.write_raw(b"-")?;
+            return Ok(None);
+        }
+
+        self.write_raw(b"+")?;
+        Ok(Some(String::from_utf8_lossy(&payload).into_owned()))
+    }
+
+    fn handle_packet(&mut self, packet: &str, emulator: &mut Emulator) -> io::Result<bool> {
+        if packet.is_empty() || !packet.is_ascii() {
+            self.send_packet("")?;
+            return Ok(true);
+        }
+
+        let (command, args) = packet.split_at(1);
+
+        match command {
+            "?" => self.send_stop_reply(SIGTRAP)?,
+            "g" => {
+                let registers: String = (0..REGS_TOTAL).map(|n| read_register(emulator, n)).collect();
+                self.send_packet(&registers)?;
+            },
+            "G" => {
+                let mut rest = args;
+                for n in 0..REGS_TOTAL {
+                    let width = register_width(n) * 2;
+                    if rest.len() < width {
+                        break;
+                    }
+                    write_register(emulator, n, &rest[..width]);
+                    rest = &rest[width..];
+                }
+                self.send_packet("OK")?;
+            },
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(n) if n < REGS_TOTAL => self.send_packet(&read_register(emulator, n))?,
+                    _ => self.send_packet("E01")?
+                }
+            },
+            "P" => {
+                let reply = match args.split_once('=') {
+                    Some((n, value)) => match usize::from_str_radix(n, 16) {
+                        Ok(n) if n < REGS_TOTAL && write_register(emulator, n, value) => "OK",
+                        _ => "E01"
+                    },
+                    None => "E01"
+                };
+                self.send_packet(reply)?;
+            },
+            "m" => {
+                let reply = match parse_memory_range(args, emulator.memory_size()) {
+                    Some((addr, len)) => (addr..addr + len).map(|a| format!("{:02x}", emulator.read_memory(a as u16))).collect(),
+                    None => "E01".to_string()
+                };
+                self.send_packet(&reply)?;
+            },
+            "M" => {
+                let reply = match args.split_once(':') {
+                    Some((range, data)) => match (parse_memory_range(range, emulator.memory_size()), decode_hex(data)) {
+                        (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                            for (i, byte) in bytes.iter().enumerate() {
+                                emulator.write_memory((addr + i) as u16, *byte);
+                            }
+                            "OK"
+                        },
+                        _ => "E01"
+                    },
+                    None => "E01"
+                };
+                self.send_packet(reply)?;
+            },
+            "Z" | "z" => {
+                let fields: Vec<&str> = args.split(',').collect();
+                let addr = fields.get(1).and_then(|addr| u16::from_str_radix(addr, 16).ok());
+                let len = fields.get(2).and_then(|len| u16::from_str_radix(len, 16).ok()).unwrap_or(1).max(1);
+                let insert = command == "Z";
+
+                let kinds: &[WatchKind] = match fields[0] {
+                    "2" => &[WatchKind::Write],
+                    "3" => &[WatchKind::Read],
+                    "4" => &[WatchKind::Read, WatchKind::Write],
+                    _ => &[]
+                };
+
+                match (fields[0], addr) {
+                    ("0", Some(addr)) | ("1", Some(addr)) => {
+                        if insert {
+                            if !self.breakpoints.contains(&addr) {
+                                self.breakpoints.push(addr);
+                            }
+                        } else {
+                            self.breakpoints.retain(|bp| *bp != addr);
+                        }
+                        self.send_packet("OK")?;
+                    },
+                    (_, Some(addr)) if !kinds.is_empty() => {
+                        let end = addr.saturating_add(len - 1);
+                        for kind in kinds {
+                            if insert {
+                                emulator.add_watchpoint(addr, end, *kind);
+                            } else {
+                                emulator.remove_watchpoint(addr, end, *kind);
+                            }
+                        }
+                        self.send_packet("OK")?;
+                    },
+                    _ => self.send_packet("")?
+                }
+            },
+            "c" => self.resume(emulator, args),
+            "s" => self.step(emulator, args)?,
+            "v" => {
+                if packet == "vCont?" {
+                    self.send_packet("vCont;c;C;s;S")?;
+                } else if let Some(action) = packet.strip_prefix("vCont;") {
+                    match action.chars().next() {
+                        Some('c') | Some('C') => self.resume(emulator, ""),
+                        Some('s') | Some('S') => self.step(emulator, "")?,
+                        _ => self.send_packet("")?
+                    }
+                } else {
+                    self.send_packet("")?;
+                }
+            },
+            "q" => self.handle_query(packet)?,
+            "H" => self.send_packet("OK")?,
+            "T" => self.send_packet("OK")?,
+            "D" => {
+                self.breakpoints.clear();
+                self.running = true;
+                self.send_packet("OK")?;
+            },
+            "k" => return Ok(false),
+            _ => self.send_packet("")?
+        }
+
+        Ok(true)
+    }
+
+    fn handle_query(&mut self, packet: &str) -> io::Result<()> {
+        if packet.starts_with("qSupported") {
+            self.send_packet("PacketSize=1000;qXfer:features:read+")
+        } else if packet == "qAttached" {
+            self.send_packet("1")
+        } else if packet == "qC" {
+            self.send_packet("QC1")
+        } else if packet == "qfThreadInfo" {
+            self.send_packet("m1")
+        } else if packet == "qsThreadInfo" {
+            self.send_packet("l")
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_description();
+            let reply = match range.split_once(',') {
+                Some((offset, len)) => match (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16)) {
+                    (Ok(offset), Ok(len)) if offset < xml.len() => {
+                        let end = (offset + len).min(xml.len());
+                        let marker = if end == xml.len() {"l"} else {"m"};
+                        format!("{}{}", marker, &xml[offset..end])
+                    },
+                    (Ok(_), Ok(_)) => "l".to_string(),
+                    _ => "E01".to_string()
+                },
+                None => "E01".to_string()
+            };
+            self.send_packet(&reply)
+        } else {
+            self.send_packet("")
+        }
+    }
+
+    fn resume(&mut self, emulator: &mut Emulator, addr: &str) {
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            emulator.set_pc(addr);
+        }
+
+        self.running = true;
+        self.skip_breakpoint = true;
+    }
+
+    fn step(&mut self, emulator: &mut Emulator, addr: &str) -> io::Result<()> {
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            emulator.set_pc(addr);
+        }
+
+        if emulator.run_instruction().is_err() {
+            return self.send_stop_reply(SIGILL);
+        }
+
+        match emulator.take_watch_hit() {
+            Some(hit) => self.report_watch_hit(hit),
+            None => self.send_stop_reply(SIGTRAP)
+        }
+    }
+
+    pub fn report_watch_hit(&mut self, hit: WatchHit) -> io::Result<()> {
+        self.running = false;
+
+        let reason = match hit.kind {
+            WatchKind::Read => "rwatch",
+            WatchKind::Write | WatchKind::Change => "watch",
+            WatchKind::Fault => return self.send_stop_reply(SIGSEGV)
+        };
+
+        self.send_packet(&format!("T{:02x}{}:{:04x};", SIGTRAP, reason, hit.addr))
+    }
+
+    // The program stopped on an instruction that can not run.
+    pub fn report_cpu_error(&mut self) -> io::Result<()> {
+        self.running = false;
+
+        self.send_stop_reply(SIGILL)
+    }
+
+    fn send_stop_reply(&mut self, signal: u8) -> io::Result<()> {
+        self.send_packet(&format!("S{:02x}", signal))
+    }
+
+    fn send_packet(&mut self, payload: &str) -> io::Result<()> {
+        let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.write_raw(format!("${}#{:02x}", payload, checksum).as_bytes())
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(data);
+        self.stream.set_nonblocking(true)?;
+
+        result
+    }
+}
+
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1
+    }
+}
+
+// CHIP-8 is big-endian, so 16-bit registers are sent most significant byte first.
+fn read_register(emulator: &Emulator, n: usize) -> String {
+    match n {
+        REG_I => format!("{:04x}", emulator.get_index_reg()),
+        REG_PC => format!("{:04x}", emulator.get_pc()),
+        REG_SP => format!("{:02x}", emulator.get_sp()),
+        REG_DT => format!("{:02x}", emulator.get_delay_timer()),
+        REG_ST => format!("{:02x}", emulator.get_sound_timer()),
+        _ => format!("{:02x}", emulator.get_register(n as u8))
+    }
+}
+
+fn write_register(emulator: &mut Emulator, n: usize, hex: &str) -> bool {
+    let value = match u16::from_str_radix(hex, 16) {
+        Ok(value) => value,
+        Err(_) => return false
+    };
+
+    match n {
+        REG_I => emulator.set_index_reg(value),
+        REG_PC => emulator.set_pc(value),
+        REG_SP => emulator.set_sp(value),
+        REG_DT => emulator.set_delay_timer(value as u8),
+        REG_ST => emulator.set_sound_timer(value as u8),
+        _ => emulator.set_register(n as u8, value as u8)
+    }
+
+    true
+}
+
+fn parse_memory_range(range: &str, memory_size: usize) -> Option<(usize, usize)> {
+    let (addr, len) = range.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+
+    addr.checked_add(len).filter(|end| *end <= memory_size)?;
+
+    Some((addr, len))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn target_description() -> String {
+    let mut registers = String::new();
+    for n in 0..REGISTERS_COUNT {
+        registers.push_str(&format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", n));
+    }
+
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><feature name=\"org.tlmc.chip8\">{}\
+        <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+        <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+        <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\
+        <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\
+        <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\
+        </feature></target>",
+        registers
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_memory_range;
+
+    #[test]
+    fn memory_ranges_stay_inside_the_memory() {
+        assert_eq!(parse_memory_range("200,10", 0x1000), Some((0x200, 0x10)));
+        assert_eq!(parse_memory_range("ff0,10", 0x1000), Some((0xFF0, 0x10)));
+        assert_eq!(parse_memory_range("ff1,10", 0x1000), None);
+        assert_eq!(parse_memory_range("1000,1", 0x1000), None);
+        assert_eq!(parse_memory_range("ffffffffffffffff,1", 0x1000), None);
+        assert_eq!(parse_memory_range("1,ffffffffffffffff", 0x1000), None);
+        assert_eq!(parse_memory_range("200", 0x1000), None);
+    }
+}
@@ -0,0 +1,251 @@
+// Minimal GIF decoder : reads the frames of an image as palette indices, which is all
+// the Octo cartridges need. Colours, disposal and transparency are ignored.
+
+pub struct Frame {
+    // Row by row, fewer than width * height when the image data stops early.
+    pub pixels: Vec<u8>
+}
+
+pub fn decode_frames(data: &[u8]) -> Result<Vec<Frame>, String> {
+    let mut reader = Reader { data, pos: 0 };
+
+    let signature = reader.bytes(6)?;
+    if signature != b"GIF87a" && signature != b"GIF89a" {
+        return Err("not a GIF image".to_string());
+    }
+
+    // logical screen descriptor.
+    reader.bytes(4)?;
+    let flags = reader.byte()?;
+    reader.bytes(2)?;
+    if flags & 0x80 != 0 {
+        reader.bytes(3 << ((flags & 0x07) + 1))?;
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        match reader.byte()? {
+            // extension : skipped.
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            },
+            // image descriptor.
+            0x2C => {
+                reader.bytes(4)?;
+                let width = reader.u16()? as usize;
+                let height = reader.u16()? as usize;
+                let flags = reader.byte()?;
+                if flags & 0x80 != 0 {
+                    reader.bytes(3 << ((flags & 0x07) + 1))?;
+                }
+
+                let min_code_size = reader.byte()?;
+                let compressed = reader.sub_blocks()?;
+                let mut pixels = lzw_decode(&compressed, min_code_size, width * height)?;
+                if flags & 0x40 != 0 {
+                    if pixels.len() < width * height {
+                        return Err("truncated GIF image".to_string());
+                    }
+                    pixels = deinterlace(&pixels, width, height);
+                }
+
+                frames.push(Frame { pixels });
+            },
+            // trailer.
+            0x3B => break,
+            block => return Err(format!("unknown GIF block 0x{:02X}", block))
+        }
+    }
+
+    Ok(frames)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + count).ok_or("truncated GIF image")?;
+        self.pos += count;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Data sub-blocks, up to the empty block that ends them.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        loop {
+            let size = self.byte()? as usize;
+            if size == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.bytes(size)?);
+        }
+    }
+}
+
+// Decodes up to max_pixels pixels. The output grows with the decoded codes rather than
+// with the size in the header, which a few bytes can set to 65535x65535.
+fn lzw_decode(data: &[u8], min_code_size: u8, max_pixels: usize) -> Result<Vec<u8>, String> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err(format!("invalid LZW code size {}", min_code_size));
+    }
+
+    let clear_code = 1usize << min_code_size;
+    let end_code = clear_code + 1;
+
+    // each entry is (prefix code, last byte), the dictionary roots being the single bytes.
+    let mut dictionary: Vec<(Option<usize>, u8)> = Vec::with_capacity(4096);
+    let reset = |dictionary: &mut Vec<(Option<usize>, u8)>| {
+        dictionary.clear();
+        dictionary.extend((0..clear_code + 2).map(|code| (None, code as u8)));
+    };
+    reset(&mut dictionary);
+
+    let mut code_size = min_code_size as u32 + 1;
+    let mut previous: Option<usize> = None;
+    let mut pixels = Vec::new();
+    let mut entry = Vec::new();
+
+    let mut bit_pos = 0;
+    while bit_pos + code_size as usize <= data.len() * 8 && pixels.len() < max_pixels {
+        let mut code = 0;
+        for i in 0..code_size as usize {
+            let bit = (data[(bit_pos + i) / 8] >> ((bit_pos + i) % 8)) & 1;
+            code |= (bit as usize) << i;
+        }
+        bit_pos += code_size as usize;
+
+        if code == clear_code {
+            reset(&mut dictionary);
+            code_size = min_code_size as u32 + 1;
+            previous = None;
+            continue;
+        }
+        if code == end_code {
+            break;
+        }
+
+        let first_byte = match (code < dictionary.len(), previous) {
+            (true, _) => {
+                expand(&dictionary, code, &mut entry);
+                entry[0]
+            },
+            // the code being defined : previous entry followed by its own first byte.
+            (false, Some(previous)) if code == dictionary.len() => {
+                expand(&dictionary, previous, &mut entry);
+                let first_byte = entry[0];
+                entry.push(first_byte);
+                first_byte
+            },
+            _ => return Err("invalid LZW code".to_string())
+        };
+        pixels.extend_from_slice(&entry);
+
+        if let Some(previous) = previous {
+            if dictionary.len() < 4096 {
+                dictionary.push((Some(previous), first_byte));
+                if dictionary.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+        }
+        previous = Some(code);
+    }
+
+    pixels.truncate(max_pixels);
+    Ok(pixels)
+}
+
+fn expand(dictionary: &[(Option<usize>, u8)], code: usize, entry: &mut Vec<u8>) {
+    entry.clear();
+
+    let mut code = Some(code);
+    while let Some(current) = code {
+        let (prefix, byte) = dictionary[current];
+        entry.push(byte);
+        code = prefix;
+    }
+
+    entry.reverse();
+}
+
+// Interlaced rows are stored in 4 passes : every 8th row from 0, every 8th from 4,
+// every 4th from 2 and every 2nd from 1.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut rows = Vec::with_capacity(height);
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        rows.extend((start..height).step_by(step));
+    }
+
+    let mut output = vec![0; width * height];
+    for (i, row) in rows.into_iter().enumerate() {
+        output[row * width..(row + 1) * width].copy_from_slice(&pixels[i * width..(i + 1) * width]);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_frames, deinterlace, lzw_decode};
+
+    // 1 1 1 1 2 with 2-bit colours : clear, 1, 6 (1 1, the code being defined), 1, then
+    // 2 and end on 4 bits once the dictionary holds 8 entries.
+    const PIXELS: [u8; 3] = [0x8C, 0x23, 0x05];
+
+    #[test]
+    fn lzw_codes_are_expanded() {
+        assert_eq!(lzw_decode(&PIXELS, 2, 5).unwrap(), [1, 1, 1, 1, 2]);
+        assert_eq!(lzw_decode(&PIXELS, 2, 3).unwrap(), [1, 1, 1]);
+        // the data stops before the image is complete.
+        assert_eq!(lzw_decode(&PIXELS, 2, 100).unwrap(), [1, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn invalid_lzw_data_is_an_error() {
+        // clear, then 7 which is not defined yet.
+        assert!(lzw_decode(&[0x3C], 2, 4).is_err());
+        assert!(lzw_decode(&PIXELS, 0, 5).is_err());
+        assert!(lzw_decode(&PIXELS, 12, 5).is_err());
+    }
+
+    #[test]
+    fn image_size_is_not_trusted() {
+        // a 65535x65535 image holding only a clear and an end code.
+        let mut data = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+        data.extend_from_slice(b"\x2C\x00\x00\x00\x00\xFF\xFF\xFF\xFF\x00\x02\x01\x2C\x00\x3B");
+
+        let frames = decode_frames(&data).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].pixels.is_empty());
+
+        // interlaced, the rows are missing.
+        data[22] = 0x40;
+        assert!(decode_frames(&data).is_err());
+    }
+
+    #[test]
+    fn truncated_images_are_errors() {
+        assert!(decode_frames(b"GIF89a\x01\x00").is_err());
+        assert!(decode_frames(b"PNG").is_err());
+    }
+
+    #[test]
+    fn interlaced_rows_are_reordered() {
+        // 4 rows stored as 0, 2, 1, 3.
+        assert_eq!(deinterlace(&[0, 2, 1, 3], 1, 4), [0, 1, 2, 3]);
+    }
+}
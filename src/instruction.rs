@@ -0,0 +1,338 @@
+use std::fmt;
+
+// The CHIP-8 instructions, named after their mnemonics in Cowgod's reference. Registers
+// are register numbers (X, Y), addresses are 12 bits (NNN).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    // 0NNN : machine code routine of the original interpreter.
+    Sys(u16),
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 1NNN
+    Jp(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    SeImm(u8, u8),
+    // 4XNN
+    SneImm(u8, u8),
+    // 5XY0
+    SeReg(u8, u8),
+    // 6XNN
+    LdImm(u8, u8),
+    // 7XNN
+    AddImm(u8, u8),
+    // 8XY0
+    LdReg(u8, u8),
+    // 8XY1
+    Or(u8, u8),
+    // 8XY2
+    And(u8, u8),
+    // 8XY3
+    Xor(u8, u8),
+    // 8XY4
+    AddReg(u8, u8),
+    // 8XY5
+    Sub(u8, u8),
+    // 8XY6
+    Shr(u8, u8),
+    // 8XY7
+    Subn(u8, u8),
+    // 8XYE
+    Shl(u8, u8),
+    // 9XY0
+    SneReg(u8, u8),
+    // ANNN
+    LdI(u16),
+    // BNNN, BXNN with the jump quirk.
+    JpV0(u16),
+    // CXNN
+    Rnd(u8, u8),
+    // DXYN
+    Drw(u8, u8, u8),
+    // EX9E
+    Skp(u8),
+    // EXA1
+    Sknp(u8),
+    // FX07
+    LdVxDt(u8),
+    // FX0A
+    LdVxK(u8),
+    // FX15
+    LdDtVx(u8),
+    // FX18
+    LdStVx(u8),
+    // FX1E
+    AddI(u8),
+    // FX29
+    LdF(u8),
+    // FX30, SUPER-CHIP : the 8x10 digit.
+    LdHf(u8),
+    // FX33
+    LdB(u8),
+    // FX55
+    LdIVx(u8),
+    // FX65
+    LdVxI(u8)
+}
+
+// The opcodes decode accepts. The SUPER-CHIP instructions are unknown opcodes on CHIP-8.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum InstructionSet {
+    #[default]
+    Chip8,
+    Schip
+}
+
+impl InstructionSet {
+    // The instruction set of a quirk preset : SUPER-CHIP for schip and for xochip, which
+    // extends it.
+    pub fn from_preset(name: &str) -> InstructionSet {
+        match name {
+            "schip" | "xochip" => InstructionSet::Schip,
+            _ => InstructionSet::Chip8
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DecodeError {
+    pub opcode: u16
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown opcode -> 0x{:04X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(opcode: u16, instruction_set: InstructionSet) -> Result<Instruction, DecodeError> {
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let schip = instruction_set == InstructionSet::Schip;
+
+    let instruction = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            _ => Instruction::Sys(nnn)
+        },
+        0x1000 => Instruction::Jp(nnn),
+        0x2000 => Instruction::Call(nnn),
+        0x3000 => Instruction::SeImm(x, nn),
+        0x4000 => Instruction::SneImm(x, nn),
+        0x5000 if n == 0x0 => Instruction::SeReg(x, y),
+        0x6000 => Instruction::LdImm(x, nn),
+        0x7000 => Instruction::AddImm(x, nn),
+        0x8000 => match n {
+            0x0 => Instruction::LdReg(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddReg(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::Shr(x, y),
+            0x7 => Instruction::Subn(x, y),
+            0xE => Instruction::Shl(x, y),
+            _ => return Err(DecodeError { opcode })
+        },
+        0x9000 if n == 0x0 => Instruction::SneReg(x, y),
+        0xA000 => Instruction::LdI(nnn),
+        0xB000 => Instruction::JpV0(nnn),
+        0xC000 => Instruction::Rnd(x, nn),
+        0xD000 => Instruction::Drw(x, y, n),
+        0xE000 => match nn {
+            0x9E => Instruction::Skp(x),
+            0xA1 => Instruction::Sknp(x),
+            _ => return Err(DecodeError { opcode })
+        },
+        0xF000 => match nn {
+            0x07 => Instruction::LdVxDt(x),
+            0x0A => Instruction::LdVxK(x),
+            0x15 => Instruction::LdDtVx(x),
+            0x18 => Instruction::LdStVx(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LdF(x),
+            0x30 if schip => Instruction::LdHf(x),
+            0x33 => Instruction::LdB(x),
+            0x55 => Instruction::LdIVx(x),
+            0x65 => Instruction::LdVxI(x),
+            _ => return Err(DecodeError { opcode })
+        },
+        _ => return Err(DecodeError { opcode })
+    };
+
+    Ok(instruction)
+}
+
+impl Instruction {
+    pub fn encode(self) -> u16 {
+        let xy = |opcode: u16, x: u8, y: u8| opcode | (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |opcode: u16, x: u8, nn: u8| opcode | (x as u16) << 8 | nn as u16;
+        let x_only = |opcode: u16, x: u8| opcode | (x as u16) << 8;
+
+        match self {
+            Instruction::Sys(nnn) => nnn & 0x0FFF,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Jp(nnn) => 0x1000 | nnn & 0x0FFF,
+            Instruction::Call(nnn) => 0x2000 | nnn & 0x0FFF,
+            Instruction::SeImm(x, nn) => xnn(0x3000, x, nn),
+            Instruction::SneImm(x, nn) => xnn(0x4000, x, nn),
+            Instruction::SeReg(x, y) => xy(0x5000, x, y),
+            Instruction::LdImm(x, nn) => xnn(0x6000, x, nn),
+            Instruction::AddImm(x, nn) => xnn(0x7000, x, nn),
+            Instruction::LdReg(x, y) => xy(0x8000, x, y),
+            Instruction::Or(x, y) => xy(0x8001, x, y),
+            Instruction::And(x, y) => xy(0x8002, x, y),
+            Instruction::Xor(x, y) => xy(0x8003, x, y),
+            Instruction::AddReg(x, y) => xy(0x8004, x, y),
+            Instruction::Sub(x, y) => xy(0x8005, x, y),
+            Instruction::Shr(x, y) => xy(0x8006, x, y),
+            Instruction::Subn(x, y) => xy(0x8007, x, y),
+            Instruction::Shl(x, y) => xy(0x800E, x, y),
+            Instruction::SneReg(x, y) => xy(0x9000, x, y),
+            Instruction::LdI(nnn) => 0xA000 | nnn & 0x0FFF,
+            Instruction::JpV0(nnn) => 0xB000 | nnn & 0x0FFF,
+            Instruction::Rnd(x, nn) => xnn(0xC000, x, nn),
+            Instruction::Drw(x, y, n) => xy(0xD000, x, y) | (n & 0xF) as u16,
+            Instruction::Skp(x) => x_only(0xE09E, x),
+            Instruction::Sknp(x) => x_only(0xE0A1, x),
+            Instruction::LdVxDt(x) => x_only(0xF007, x),
+            Instruction::LdVxK(x) => x_only(0xF00A, x),
+            Instruction::LdDtVx(x) => x_only(0xF015, x),
+            Instruction::LdStVx(x) => x_only(0xF018, x),
+            Instruction::AddI(x) => x_only(0xF01E, x),
+            Instruction::LdF(x) => x_only(0xF029, x),
+            Instruction::LdHf(x) => x_only(0xF030, x),
+            Instruction::LdB(x) => x_only(0xF033, x),
+            Instruction::LdIVx(x) => x_only(0xF055, x),
+            Instruction::LdVxI(x) => x_only(0xF065, x)
+        }
+    }
+
+    // The opcode pattern, e.g. "8XY4".
+    pub fn family(self) -> &'static str {
+        match self {
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Jp(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SeImm(..) => "3XNN",
+            Instruction::SneImm(..) => "4XNN",
+            Instruction::SeReg(..) => "5XY0",
+            Instruction::LdImm(..) => "6XNN",
+            Instruction::AddImm(..) => "7XNN",
+            Instruction::LdReg(..) => "8XY0",
+            Instruction::Or(..) => "8XY1",
+            Instruction::And(..) => "8XY2",
+            Instruction::Xor(..) => "8XY3",
+            Instruction::AddReg(..) => "8XY4",
+            Instruction::Sub(..) => "8XY5",
+            Instruction::Shr(..) => "8XY6",
+            Instruction::Subn(..) => "8XY7",
+            Instruction::Shl(..) => "8XYE",
+            Instruction::SneReg(..) => "9XY0",
+            Instruction::LdI(_) => "ANNN",
+            Instruction::JpV0(_) => "BNNN",
+            Instruction::Rnd(..) => "CXNN",
+            Instruction::Drw(..) => "DXYN",
+            Instruction::Skp(_) => "EX9E",
+            Instruction::Sknp(_) => "EXA1",
+            Instruction::LdVxDt(_) => "FX07",
+            Instruction::LdVxK(_) => "FX0A",
+            Instruction::LdDtVx(_) => "FX15",
+            Instruction::LdStVx(_) => "FX18",
+            Instruction::AddI(_) => "FX1E",
+            Instruction::LdF(_) => "FX29",
+            Instruction::LdHf(_) => "FX30",
+            Instruction::LdB(_) => "FX33",
+            Instruction::LdIVx(_) => "FX55",
+            Instruction::LdVxI(_) => "FX65"
+        }
+    }
+}
+
+// Cowgod's syntax, as read by the assembler.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SeImm(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SneImm(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdImm(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddImm(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            // VY only matters without the shift quirk, it is omitted when it is V0.
+            Instruction::Shr(x, 0) => write!(f, "SHR V{:X}", x),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, 0) => write!(f, "SHL V{:X}", x),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Rnd(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_decoded_opcode_encodes_back() {
+        for instruction_set in [InstructionSet::Chip8, InstructionSet::Schip] {
+            for opcode in 0..=0xFFFF {
+                if let Ok(instruction) = decode(opcode, instruction_set) {
+                    assert_eq!(instruction.encode(), opcode, "{}", instruction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn schip_instructions_need_the_schip_set() {
+        assert_eq!(decode(0xF330, InstructionSet::Chip8), Err(DecodeError { opcode: 0xF330 }));
+        assert_eq!(decode(0xF330, InstructionSet::Schip), Ok(Instruction::LdHf(0x3)));
+
+        // CHIP-8 is a subset of SUPER-CHIP.
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode, InstructionSet::Chip8) {
+                assert_eq!(decode(opcode, InstructionSet::Schip), Ok(instruction));
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::instruction::{Instruction, InstructionSet};
+
+// Decoded instructions by address, so that a loop is decoded once instead of on every
+// pass. An entry covers the two bytes of its instruction : a write to either byte drops
+// it, which keeps self-modifying programs correct.
+#[derive(Clone)]
+pub struct InstructionCache {
+    entries: Vec<Option<Instruction>>,
+    enabled: bool,
+    // The set the entries were decoded with.
+    instruction_set: InstructionSet
+}
+
+impl InstructionCache {
+    pub fn new(memory_size: usize) -> InstructionCache {
+        InstructionCache {
+            entries: vec![None; memory_size],
+            enabled: true,
+            instruction_set: InstructionSet::default()
+        }
+    }
+
+    // A disabled cache decodes every instruction, as the plain interpreter.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    pub fn resize(&mut self, memory_size: usize) {
+        self.entries = vec![None; memory_size];
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+
+    // Another instruction set drops the instructions decoded with the previous one.
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) {
+        if instruction_set != self.instruction_set {
+            self.instruction_set = instruction_set;
+            self.clear();
+        }
+    }
+
+    pub fn get(&self, addr: u16) -> Option<Instruction> {
+        self.entries.get(addr as usize).copied().flatten()
+    }
+
+    // Instructions wrapping around the end of memory are not cached.
+    pub fn insert(&mut self, addr: u16, instruction: Instruction) {
+        if self.enabled && (addr as usize) + 1 < self.entries.len() {
+            self.entries[addr as usize] = Some(instruction);
+        }
+    }
+
+    // The byte at the address was written : drops the instructions holding it.
+    pub fn invalidate(&mut self, addr: usize) {
+        if let Some(entry) = self.entries.get_mut(addr) {
+            *entry = None;
+        }
+        if let Some(entry) = addr.checked_sub(1).and_then(|addr| self.entries.get_mut(addr)) {
+            *entry = None;
+        }
+    }
+}
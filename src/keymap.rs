@@ -0,0 +1,39 @@
+extern crate sdl2;
+
+use sdl2::keyboard::Keycode;
+
+pub struct Keymap {
+    bindings: Vec<(Keycode, u8)>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            bindings: vec![
+                (Keycode::Num1, 0x1), (Keycode::Num2, 0x2), (Keycode::Num3, 0x3), (Keycode::Num4, 0xC),
+                (Keycode::Q, 0x4), (Keycode::W, 0x5), (Keycode::E, 0x6), (Keycode::R, 0xD),
+                (Keycode::A, 0x7), (Keycode::S, 0x8), (Keycode::D, 0x9), (Keycode::F, 0xE),
+                (Keycode::Z, 0xA), (Keycode::X, 0x0), (Keycode::C, 0xB), (Keycode::V, 0xF),
+            ]
+        }
+    }
+
+    // Replaces the binding of a CHIP-8 key, e.g. bind("5", "Up").
+    pub fn bind(&mut self, key: &str, key_name: &str) -> Result<(), String> {
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(key) if key <= 0xF => key,
+            _ => return Err(format!("Invalid CHIP-8 key -> {}", key))
+        };
+
+        let keycode = Keycode::from_name(key_name).ok_or_else(|| format!("Unknown key name -> {}", key_name))?;
+
+        self.bindings.retain(|(bound_keycode, bound_key)| *bound_key != key && *bound_keycode != keycode);
+        self.bindings.push((keycode, key));
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, keycode: Keycode) -> Option<u8> {
+        self.bindings.iter().find(|(bound_keycode, _)| *bound_keycode == keycode).map(|(_, key)| *key)
+    }
+}
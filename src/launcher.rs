@@ -0,0 +1,406 @@
+extern crate sdl2;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sdl2::controller::Button;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use serde::{Deserialize, Serialize};
+
+use tlmc::rom_database::{self, RomDatabase};
+use tlmc::rom_loader;
+use crate::text::{draw_text, CHAR_WIDTH, LINE_HEIGHT};
+
+const MAX_RECENT: usize = 10;
+const MARGIN: i32 = 8;
+const LIST_TOP: i32 = MARGIN + 2 * LINE_HEIGHT;
+const REFRESH_DELAY: Duration = Duration::from_millis(16);
+
+const TEXT_COLOR: Color = Color::RGB(200, 200, 200);
+const TITLE_COLOR: Color = Color::RGB(255, 255, 0);
+const SELECTED_COLOR: Color = Color::RGB(0, 255, 0);
+const STATUS_COLOR: Color = Color::RGB(255, 80, 80);
+
+// Saved to launcher.toml in the configuration directory.
+#[derive(Serialize, Deserialize, Default)]
+struct LauncherSettings {
+    rom_dir: Option<PathBuf>,
+    #[serde(default)]
+    recent: Vec<String>,
+    #[serde(default)]
+    favourites: Vec<String>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RomList {
+    All,
+    Recent,
+    Favourites
+}
+
+struct RomEntry {
+    path: String,
+    title: String,
+    platform: String
+}
+
+// A ROM file, or the entry picked in a zip archive holding several ROMs.
+pub struct RomChoice {
+    pub path: String,
+    pub entry: Option<String>
+}
+
+pub struct Launcher<'a> {
+    database: &'a RomDatabase,
+    settings: LauncherSettings,
+    rom_dir: PathBuf,
+    list: RomList,
+    // The zip archive whose ROMs are listed instead of the list, if any.
+    archive: Option<String>,
+    entries: Vec<RomEntry>,
+    selected: usize,
+    scroll: usize,
+    status: String
+}
+
+impl<'a> Launcher<'a> {
+    // The ROM directory is the given one, else the one from launcher.toml, else the current directory.
+    pub fn new(rom_dir: Option<PathBuf>, database: &'a RomDatabase) -> Launcher<'a> {
+        let settings = load_settings();
+        let rom_dir = rom_dir.or_else(|| settings.rom_dir.clone()).unwrap_or_else(|| PathBuf::from("."));
+
+        let mut launcher = Launcher {
+            database,
+            settings,
+            rom_dir,
+            list: RomList::All,
+            archive: None,
+            entries: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            status: String::new()
+        };
+        launcher.refresh();
+
+        launcher
+    }
+
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    // Shows the launcher until a ROM is picked. Returns None when the user quits.
+    pub fn run(&mut self, canvas: &mut Canvas<Window>, event_pump: &mut EventPump) -> Option<RomChoice> {
+        // The lists may have changed while the previous ROM was running.
+        self.refresh();
+
+        loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } if self.archive.is_some() => self.close_archive(),
+                    Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return None,
+                    Event::KeyDown { keycode: Some(keycode), .. } => {
+                        match keycode {
+                            Keycode::Up => self.move_selection(-1, canvas),
+                            Keycode::Down => self.move_selection(1, canvas),
+                            Keycode::PageUp => self.move_selection(-(visible_rows(canvas) as i32), canvas),
+                            Keycode::PageDown => self.move_selection(visible_rows(canvas) as i32, canvas),
+                            Keycode::Home => self.move_selection(-(self.entries.len() as i32), canvas),
+                            Keycode::End => self.move_selection(self.entries.len() as i32, canvas),
+                            Keycode::Tab => self.next_list(),
+                            Keycode::F => self.toggle_favourite(),
+                            Keycode::Return | Keycode::KpEnter => {
+                                if let Some(path) = self.play() {
+                                    return Some(path);
+                                }
+                            },
+                            _ => {}
+                        }
+                    },
+                    Event::DropFile { filename, .. } => {
+                        if let Some(choice) = self.open(filename) {
+                            return Some(choice);
+                        }
+                    },
+                    Event::ControllerButtonDown { button, .. } => {
+                        match button {
+                            Button::DPadUp => self.move_selection(-1, canvas),
+                            Button::DPadDown => self.move_selection(1, canvas),
+                            Button::DPadLeft | Button::LeftShoulder => self.move_selection(-(visible_rows(canvas) as i32), canvas),
+                            Button::DPadRight | Button::RightShoulder => self.move_selection(visible_rows(canvas) as i32, canvas),
+                            Button::X | Button::Back => self.next_list(),
+                            Button::Y => self.toggle_favourite(),
+                            Button::B => self.close_archive(),
+                            Button::A | Button::Start => {
+                                if let Some(path) = self.play() {
+                                    return Some(path);
+                                }
+                            },
+                            _ => {}
+                        }
+                    },
+                    _ => {}
+                }
+            }
+
+            self.draw(canvas);
+            std::thread::sleep(REFRESH_DELAY);
+        }
+    }
+
+    fn refresh(&mut self) {
+        if let Some(archive) = &self.archive {
+            let names = rom_loader::list_archive_roms(archive).unwrap_or_default();
+            self.entries = names.into_iter().map(|name| describe_archive_rom(archive, name, self.database)).collect();
+            self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+            self.scroll = self.scroll.min(self.selected);
+            return;
+        }
+
+        let paths = match self.list {
+            RomList::All => list_rom_dir(&self.rom_dir),
+            RomList::Recent => self.settings.recent.clone(),
+            RomList::Favourites => self.settings.favourites.clone()
+        };
+
+        self.entries = paths.into_iter().map(|path| describe_rom(path, self.database)).collect();
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+        self.scroll = self.scroll.min(self.selected);
+    }
+
+    fn move_selection(&mut self, delta: i32, canvas: &Canvas<Window>) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let last = self.entries.len() as i32 - 1;
+        self.selected = (self.selected as i32 + delta).clamp(0, last) as usize;
+
+        let rows = visible_rows(canvas);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+    }
+
+    fn next_list(&mut self) {
+        self.archive = None;
+        self.list = match self.list {
+            RomList::All => RomList::Recent,
+            RomList::Recent => RomList::Favourites,
+            RomList::Favourites => RomList::All
+        };
+        self.selected = 0;
+        self.scroll = 0;
+        self.refresh();
+    }
+
+    fn toggle_favourite(&mut self) {
+        // the favourites are files, not archive entries.
+        let Some(entry) = self.entries.get(self.selected).filter(|_| self.archive.is_none()) else {
+            return;
+        };
+
+        let favourites = &mut self.settings.favourites;
+        match favourites.iter().position(|path| *path == entry.path) {
+            Some(index) => {
+                favourites.remove(index);
+            },
+            None => favourites.push(entry.path.clone())
+        }
+        self.save_settings();
+
+        if self.list == RomList::Favourites {
+            self.refresh();
+        }
+    }
+
+    fn play(&mut self) -> Option<RomChoice> {
+        let path = self.entries.get(self.selected)?.path.clone();
+
+        match &self.archive {
+            Some(archive) => Some(RomChoice { path: archive.clone(), entry: Some(path) }),
+            None => self.open(path)
+        }
+    }
+
+    // Moves the ROM to the top of the recent list. A zip archive holding several ROMs is
+    // opened to pick one of them, any other file is returned.
+    fn open(&mut self, path: String) -> Option<RomChoice> {
+        self.add_recent(&path);
+
+        if rom_loader::is_archive(&path) && rom_loader::list_archive_roms(&path).is_ok_and(|names| names.len() > 1) {
+            self.archive = Some(path);
+            self.selected = 0;
+            self.scroll = 0;
+            self.refresh();
+            return None;
+        }
+
+        Some(RomChoice { path, entry: None })
+    }
+
+    fn close_archive(&mut self) {
+        if self.archive.take().is_some() {
+            self.selected = 0;
+            self.scroll = 0;
+            self.refresh();
+        }
+    }
+
+    fn add_recent(&mut self, path: &str) {
+        self.settings.recent.retain(|recent| recent != path);
+        self.settings.recent.insert(0, path.to_string());
+        self.settings.recent.truncate(MAX_RECENT);
+        self.save_settings();
+    }
+
+    fn save_settings(&mut self) {
+        if let Err(why) = save_settings(&self.settings) {
+            println!("Error : Unable to save the launcher settings !");
+            println!("Why -> {}", why);
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<Window>) {
+        let (width, height) = canvas.output_size().unwrap();
+        let columns = (width as i32 - 2 * MARGIN) / CHAR_WIDTH;
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+
+        let list_name = match (&self.archive, self.list) {
+            (Some(archive), _) => Path::new(archive).file_name().map_or_else(|| archive.clone(), |name| name.to_string_lossy().into_owned()),
+            (None, RomList::All) => "ALL ROMS".to_string(),
+            (None, RomList::Recent) => "RECENT".to_string(),
+            (None, RomList::Favourites) => "FAVOURITES".to_string()
+        };
+        draw_text(canvas, &format!("TLMC - {} ({})", list_name, self.entries.len()), MARGIN, MARGIN, TITLE_COLOR);
+
+        if self.entries.is_empty() {
+            let message = match (&self.archive, self.list) {
+                (Some(_), _) => "NO ROM IN THIS ARCHIVE".to_string(),
+                (None, RomList::All) => format!("NO ROM IN {}", self.rom_dir.display()),
+                (None, RomList::Recent) => "NO RECENTLY PLAYED ROM".to_string(),
+                (None, RomList::Favourites) => "NO FAVOURITE - PRESS F ON A ROM".to_string()
+            };
+            draw_text(canvas, &message, MARGIN, LIST_TOP, TEXT_COLOR);
+        }
+
+        for (row, entry) in self.entries.iter().enumerate().skip(self.scroll).take(visible_rows(canvas)) {
+            let y = LIST_TOP + (row - self.scroll) as i32 * LINE_HEIGHT;
+            let selected = row == self.selected;
+
+            if selected {
+                canvas.set_draw_color(Color::RGB(40, 40, 40));
+                canvas.fill_rect(Rect::new(MARGIN - 2, y - 2, width - 2 * (MARGIN as u32 - 2), LINE_HEIGHT as u32)).unwrap();
+            }
+
+            let favourite = if self.settings.favourites.contains(&entry.path) {"*"} else {" "};
+            let platform_width = entry.platform.len() as i32 + 1;
+            let title: String = entry.title.chars().take((columns - 3 - platform_width).max(0) as usize).collect();
+            let color = if selected {SELECTED_COLOR} else {TEXT_COLOR};
+
+            draw_text(canvas, &format!("{}{} {}", if selected {">"} else {" "}, favourite, title), MARGIN, y, color);
+            draw_text(canvas, &entry.platform, MARGIN + (columns - platform_width + 1) * CHAR_WIDTH, y, color);
+        }
+
+        let footer_y = height as i32 - MARGIN - 2 * LINE_HEIGHT;
+        if !self.status.is_empty() {
+            let status: String = self.status.chars().take(columns as usize).collect();
+            draw_text(canvas, &status, MARGIN, footer_y, STATUS_COLOR);
+        }
+        let help = if self.archive.is_some() {"ENTER:PLAY  TAB:LIST  ESC:BACK"} else {"ENTER:PLAY  F:FAVOURITE  TAB:LIST  ESC:QUIT"};
+        draw_text(canvas, help, MARGIN, footer_y + LINE_HEIGHT, TEXT_COLOR);
+
+        canvas.present();
+    }
+}
+
+fn visible_rows(canvas: &Canvas<Window>) -> usize {
+    let (_, height) = canvas.output_size().unwrap();
+    let list_height = height as i32 - LIST_TOP - MARGIN - 3 * LINE_HEIGHT;
+
+    (list_height / LINE_HEIGHT).max(1) as usize
+}
+
+fn list_rom_dir(rom_dir: &Path) -> Vec<String> {
+    let Ok(dir_entries) = fs::read_dir(rom_dir) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<String> = dir_entries
+        .filter_map(|dir_entry| dir_entry.ok())
+        .map(|dir_entry| dir_entry.path())
+        .filter(|path| path.is_file() && rom_loader::is_rom_file(path))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    paths.sort_by_key(|path| path.to_ascii_lowercase());
+
+    paths
+}
+
+// Title and platform from the ROM database, falling back to the file name.
+fn describe_rom(path: String, database: &RomDatabase) -> RomEntry {
+    let info = rom_loader::read_rom(&path).ok().and_then(|rom| database.lookup(&rom_database::rom_hash(&rom)).cloned());
+    let info = info.unwrap_or_default();
+
+    let file_name = Path::new(&path).file_stem().map_or_else(|| path.clone(), |stem| stem.to_string_lossy().into_owned());
+
+    RomEntry {
+        title: info.title.unwrap_or(file_name),
+        platform: info.platform.unwrap_or_default(),
+        path
+    }
+}
+
+// Title and platform of an archive entry from the ROM database, falling back to its name.
+fn describe_archive_rom(archive: &str, name: String, database: &RomDatabase) -> RomEntry {
+    let info = rom_loader::read_rom_from_archive(archive, &name).ok().and_then(|rom| database.lookup(&rom_database::rom_hash(&rom)).cloned());
+    let info = info.unwrap_or_default();
+
+    RomEntry {
+        title: info.title.unwrap_or_else(|| name.clone()),
+        platform: info.platform.unwrap_or_default(),
+        path: name
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    rom_database::config_dir().map(|dir| dir.join("launcher.toml"))
+}
+
+fn load_settings() -> LauncherSettings {
+    let Some(path) = settings_path() else {
+        return LauncherSettings::default();
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content).unwrap_or_else(|why| {
+            println!("Error : Unable to parse the launcher settings {} !", path.display());
+            println!("Why -> {}", why);
+            LauncherSettings::default()
+        }),
+        Err(_) => LauncherSettings::default()
+    }
+}
+
+fn save_settings(settings: &LauncherSettings) -> Result<(), String> {
+    let path = settings_path().ok_or("No configuration directory")?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|why| why.to_string())?;
+    }
+
+    let content = toml::to_string(settings).map_err(|why| why.to_string())?;
+    fs::write(&path, content).map_err(|why| why.to_string())
+}
@@ -0,0 +1,25 @@
+// The emulator core and its tools, shared by the tlmc binary, the benchmarks and the
+// tests.
+pub mod ram;
+pub mod display;
+pub mod cpu;
+pub mod emulator;
+pub mod disassembler;
+pub mod tracer;
+pub mod profiler;
+pub mod coverage;
+pub mod quirks;
+pub mod rom_database;
+pub mod rom_loader;
+pub mod config;
+pub mod assembler;
+pub mod gif;
+pub mod cartridge;
+pub mod octo_options;
+pub mod octo;
+pub mod font;
+pub mod bus;
+pub mod instruction;
+pub mod instruction_cache;
+pub mod save_state;
+pub mod netplay;
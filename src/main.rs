@@ -1,7 +1,9 @@
-mod ram;
-mod display;
-mod cpu;
-mod emulator;
+mod debugger;
+mod gdb_stub;
+mod keymap;
+mod text;
+mod launcher;
+mod cli;
 mod sound_system;
 
 extern crate sdl2; 
@@ -9,37 +11,110 @@ extern crate sdl2;
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+use sdl2::controller::GameController;
 
+use std::collections::HashMap;
 use std::env;
+use std::fs::{self, File};
+use std::io::Write;
 
-use crate::emulator::Emulator;
+use tlmc::cpu::CpuError;
+use tlmc::emulator::{Emulator, RomError};
+use crate::debugger::{Debugger, PANEL_WIDTH};
+use crate::gdb_stub::GdbStub;
+use tlmc::ram::{MemoryMap, WatchHit, WatchKind};
+use tlmc::tracer::Tracer;
+use tlmc::coverage::Coverage;
+use tlmc::quirks::Quirks;
+use tlmc::rom_database::{RomDatabase, RomInfo};
+use crate::keymap::Keymap;
+use crate::launcher::{Launcher, RomChoice};
+use tlmc::rom_loader::RomWatcher;
+use tlmc::config::{parse_color, Config, Settings};
+use crate::cli::{Command, NetplayRole, Options, RunLength};
+use crate::sound_system::SoundSystem;
+use tlmc::octo_options::OctoOptions;
+use tlmc::netplay::{self, NetplayHost, NetplaySession, DEFAULT_GUEST_KEYS, DEFAULT_HOST_KEYS, DEFAULT_INPUT_DELAY};
+use tlmc::font::FontStyle;
+use tlmc::{assembler, disassembler, octo, octo_options, rom_database, rom_loader, tracer};
 
 const CHIP8_WIDTH: usize = 64;
 const CHIP8_HEIGHT: usize = 32;
-const PIXEL_SCALE: usize = 10;
+const DEFAULT_PIXEL_SCALE: u32 = 10;
+// The debugger panel needs this height whatever the pixel scale.
+const MIN_PANEL_HEIGHT: u32 = 320;
 
-const WINDOW_WIDTH: u32 = (CHIP8_WIDTH * PIXEL_SCALE) as u32;
-const WINDOW_HEIGHT: u32 = (CHIP8_HEIGHT * PIXEL_SCALE) as u32;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 15;
+// Emulated frames per displayed frame while fast-forwarding.
+const FAST_FORWARD_FRAMES: u32 = 5;
 
-const CPU_CLOCK_DELAY: u64 = 1;
-const TIMER_DIVISION_CLOCK: u8 = 9;
+const DEFAULT_WINDOW_TITLE: &str = "TLMC Chip8 Emulator by Yann BOYER";
+
+struct Palette {
+    foreground: Color,
+    background: Color
+}
+
+// Settings of the running ROM, from the configuration file, the ROM database and the command line.
+struct RomSettings {
+    window_title: String,
+    instructions_per_frame: u32,
+    scale: u32,
+    palette: Palette,
+    keymap: Keymap,
+    audio: bool
+}
+
+enum SessionEnd {
+    // Escape : back to the launcher.
+    Back,
+    // The window was closed.
+    Quit
+}
 
 pub fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() <= 1 {
-        println!("Usage : ./tlmc <chip8-rom>");
+    let command = cli::parse(&args).unwrap_or_else(|why| {
+        println!("Error : {}", why);
+        println!("Run ./tlmc --help for the usage.");
         std::process::exit(1);
+    });
+
+    match command {
+        Command::Run { rom_path, options } => run(rom_path, &options),
+        Command::Disasm { rom_path, coverage_path } => disasm(&rom_path, coverage_path.as_deref()),
+        Command::Asm { source_path, output_path } => asm(&source_path, &output_path),
+        Command::Info { rom_path, options } => info(&rom_path, &options),
+        Command::Test { rom_path, frames, expect_path, options } => test(&rom_path, frames, expect_path.as_deref(), &options),
+        Command::Bench { rom_path, length, compare, options } => bench(&rom_path, length, compare, &options),
+        Command::TraceDiff { trace_a, trace_b } => trace_diff(&trace_a, &trace_b),
+        Command::Help => cli::print_usage()
     }
+}
+
+fn run(rom_path: Option<String>, options: &Options) {
+    // asked on the terminal before the window opens, the launcher lists the entries itself.
+    let choice = rom_path.map(|path| RomChoice { entry: archive_entry(&path), path });
 
-    let rom_path = &args[1];
+    let config = Config::load();
+    let database = RomDatabase::load();
+
+    let mut base_settings = config.global.clone();
+    base_settings.merge(&options.settings);
+    let scale = base_settings.scale.unwrap_or(DEFAULT_PIXEL_SCALE);
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
- 
-    let window = video_subsystem.window("TLMC Chip8 Emulator by Yann BOYER", WINDOW_WIDTH, WINDOW_HEIGHT)
+
+    let (window_width, window_height) = window_size(scale, false);
+    let window = video_subsystem.window(DEFAULT_WINDOW_TITLE, window_width, window_height)
         .position_centered()
         .build()
         .unwrap();
@@ -47,160 +122,906 @@ pub fn main() {
     let mut canvas = window.into_canvas().build().unwrap();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    
+
+    let sound_system = SoundSystem::new();
+
+    match choice {
+        Some(choice) => {
+            if let Err(why) = run_rom(&mut canvas, &mut event_pump, &sound_system, &choice, options, &database, &config) {
+                println!("Error : {} -> {}", why, choice.path);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            // keep the game controllers open for the launcher navigation.
+            let controller_subsystem = sdl_context.game_controller().unwrap();
+            let _controllers: Vec<GameController> = (0..controller_subsystem.num_joysticks().unwrap_or(0))
+                .filter(|id| controller_subsystem.is_game_controller(*id))
+                .filter_map(|id| controller_subsystem.open(id).ok())
+                .collect();
+
+            let mut launcher = Launcher::new(options.rom_dir.clone(), &database);
+            while let Some(choice) = launcher.run(&mut canvas, &mut event_pump) {
+                match run_rom(&mut canvas, &mut event_pump, &sound_system, &choice, options, &database, &config) {
+                    Ok(SessionEnd::Back) => launcher.set_status(""),
+                    Ok(SessionEnd::Quit) => break,
+                    Err(why) => {
+                        println!("Error : {} -> {}", why, choice.path);
+                        launcher.set_status(&why.to_string());
+                    }
+                }
+                canvas.window_mut().set_title(DEFAULT_WINDOW_TITLE).unwrap();
+                canvas.window_mut().set_size(window_width, window_height).unwrap();
+            }
+        }
+    }
+}
+
+fn disasm(rom_path: &str, coverage_path: Option<&str>) {
+    let coverage = coverage_path.map(|path| Coverage::load_annotations(path).unwrap_or_else(|why| {
+        println!("Error : Unable to read the coverage map !");
+        println!("Why -> {:?}", why);
+        std::process::exit(1);
+    }));
+
+    let rom = rom_loader::read_rom_entry(rom_path, archive_entry(rom_path).as_deref());
+
+    match rom {
+        Ok(rom) => {
+            for line in disassembler::disassemble_rom(&rom, coverage.as_ref()) {
+                println!("{}", line);
+            }
+        },
+        Err(why) => {
+            println!("Error : {} -> {}", why, rom_path);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn asm(source_path: &str, output_path: &str) {
+    let source = fs::read_to_string(source_path).unwrap_or_else(|why| {
+        println!("Error : Unable to read the source file -> {}", source_path);
+        println!("Why -> {:?}", why);
+        std::process::exit(1);
+    });
+
+    // Octo source files are compiled, anything else is assembled.
+    let rom = if source_path.to_ascii_lowercase().ends_with(".8o") {
+        octo::compile(&source)
+    } else {
+        assembler::assemble(&source)
+    };
+    let rom = rom.unwrap_or_else(|why| {
+        println!("Error : {}, {}", source_path, why);
+        std::process::exit(1);
+    });
+
+    if let Err(why) = fs::write(output_path, &rom) {
+        println!("Error : Unable to write the ROM -> {}", output_path);
+        println!("Why -> {:?}", why);
+        std::process::exit(1);
+    }
+
+    println!("{} bytes written to {}.", rom.len(), output_path);
+}
+
+fn info(rom_path: &str, options: &Options) {
+    let rom = rom_loader::read_rom_entry(rom_path, archive_entry(rom_path).as_deref());
+    let rom = rom.unwrap_or_else(|why| {
+        println!("Error : {} -> {}", why, rom_path);
+        std::process::exit(1);
+    });
+
+    let config = Config::load();
+    let database = RomDatabase::load();
+    let hash = rom_database::rom_hash(&rom);
+    let rom_info = database.lookup(&hash);
+    let settings = effective_settings(rom_path, Some(&hash), rom_info, &config, options);
+
+    println!("File         : {}", rom_path);
+    println!("Size         : {} bytes", rom.len());
+    println!("SHA-1        : {}", hash);
+
+    match rom_info {
+        Some(rom_info) => {
+            println!("Title        : {}", rom_info.title.as_deref().unwrap_or("-"));
+            println!("Author       : {}", rom_info.author.as_deref().unwrap_or("-"));
+            println!("Platform     : {}", rom_info.platform.as_deref().unwrap_or("-"));
+        },
+        None => println!("Not in the ROM database.")
+    }
+
+    let quirk_flags = settings.quirk_flags.describe();
+    if quirk_flags.is_empty() {
+        println!("Quirks       : {}", settings.quirks.as_deref().unwrap_or("default"));
+    } else {
+        println!("Quirks       : {} ({})", settings.quirks.as_deref().unwrap_or("default"), quirk_flags.join(", "));
+    }
+    println!("Speed        : {} instructions per frame", settings.speed.unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME));
+    println!("Scale        : {}", settings.scale.unwrap_or(DEFAULT_PIXEL_SCALE));
+    println!("Palette      : {}, {}", settings.foreground.as_deref().unwrap_or("#FFFFFF"), settings.background.as_deref().unwrap_or("#000000"));
+    println!("Audio        : {}", if settings.audio.unwrap_or(true) {"on"} else {"off"});
+    println!("Memory       : {}, out of range : {}{}", settings.memory.as_deref().unwrap_or("classic"), settings.out_of_range.as_deref().unwrap_or("ignore"), if settings.protect_interpreter.unwrap_or(false) {", interpreter protected"} else {""});
+    println!("Font         : {} at 0x{:03X}", settings.font.as_deref().unwrap_or("chip48"), settings.font_address.unwrap_or(0x0));
+
+    let mut keys: Vec<_> = settings.keys.iter().collect();
+    keys.sort();
+    for (key, key_name) in keys {
+        println!("Key {:<8} : {}", key.to_ascii_uppercase(), key_name);
+    }
+}
+
+// Runs the ROM without a window, then prints the screen. With an expected screen file,
+// exits with 1 when the screens differ.
+fn test(rom_path: &str, frames: u32, expect_path: Option<&str>, options: &Options) {
+    let (mut emulator, instructions_per_frame) = headless_emulator(rom_path, options);
+    run_uncapped(&mut emulator, instructions_per_frame, RunLength::Frames(frames), Emulator::run_instruction);
+
+    let mut screen = String::new();
+    for y in 0..CHIP8_HEIGHT {
+        for x in 0..CHIP8_WIDTH {
+            screen.push(if emulator.pixel_is_on_at(x as u8, y as u8) {'#'} else {'.'});
+        }
+        screen.push('\n');
+    }
+    print!("{}", screen);
+
+    if let Some(expect_path) = expect_path {
+        let expected = fs::read_to_string(expect_path).unwrap_or_else(|why| {
+            println!("Error : Unable to read the expected screen -> {}", expect_path);
+            println!("Why -> {:?}", why);
+            std::process::exit(2);
+        });
+
+        if expected.lines().map(|line| line.trim_end()).ne(screen.lines()) {
+            println!("The screen differs from {}.", expect_path);
+            std::process::exit(1);
+        }
+        println!("The screen matches {}.", expect_path);
+    }
+}
+
+// Runs the ROM uncapped and reports its speed, then the time spent in each opcode family.
+// With compare, the ROM also runs on the plain interpreter, without the instruction cache.
+fn bench(rom_path: &str, length: RunLength, compare: bool, options: &Options) {
+    let (mut emulator, instructions_per_frame) = headless_emulator(rom_path, options);
+    let start = Instant::now();
+    let (frames, instructions) = run_uncapped(&mut emulator, instructions_per_frame, length, Emulator::run_instruction);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let emulated = frames as f64 * FRAME_DURATION.as_secs_f64();
+    println!("{} frames, {} instructions in {:.3} s", frames, instructions, elapsed);
+    println!("{:.0} instructions per second, {:.0} frames per second, {:.1}x real time", instructions as f64 / elapsed, frames as f64 / elapsed, emulated / elapsed);
+
+    if compare {
+        let (mut emulator, _) = headless_emulator(rom_path, options);
+        emulator.set_instruction_cache(false);
+        let start = Instant::now();
+        run_uncapped(&mut emulator, instructions_per_frame, length, Emulator::run_instruction);
+        let plain_elapsed = start.elapsed().as_secs_f64();
+
+        println!("Plain interpreter : {:.0} instructions per second, the cache is {:.2}x faster", instructions as f64 / plain_elapsed, plain_elapsed / elapsed);
+    }
+
+    // A second run times the instructions one by one, the timer itself is part of the
+    // measures.
+    let mut families: HashMap<&'static str, (u64, Duration)> = HashMap::new();
+    let (mut emulator, _) = headless_emulator(rom_path, options);
+    run_uncapped(&mut emulator, instructions_per_frame, length, |emulator| {
+        let pc = emulator.get_pc();
+        let opcode = (emulator.read_memory(pc) as u16) << 8 | emulator.read_memory(pc.wrapping_add(1)) as u16;
+
+        let start = Instant::now();
+        let result = emulator.run_instruction();
+        let family = families.entry(disassembler::opcode_family(opcode)).or_default();
+        family.0 += 1;
+        family.1 += start.elapsed();
+
+        result
+    });
+
+    let mut families: Vec<(&'static str, (u64, Duration))> = families.into_iter().collect();
+    families.sort_by(|a, b| b.1.1.cmp(&a.1.1).then(a.0.cmp(b.0)));
+    let total: Duration = families.iter().map(|(_, (_, time))| *time).sum();
+
+    println!();
+    println!("Opcode families :");
+    println!("  FAMILY       COUNT   NS/INSTR    TIME %");
+    for (family, (count, time)) in families {
+        println!("  {}    {:10}  {:9.1}  {:7.1}%", family, count, time.as_nanos() as f64 / count as f64, time.as_secs_f64() * 100.0 / total.as_secs_f64());
+    }
+}
+
+// Loads the ROM with its settings, for the runs without a window. Returns the emulator
+// and its speed in instructions per frame.
+fn headless_emulator(rom_path: &str, options: &Options) -> (Emulator, u32) {
+    let config = Config::load();
+    let database = RomDatabase::load();
+
+    let mut emulator = Emulator::new();
+    if let Err(why) = load_rom(&mut emulator, rom_path, archive_entry(rom_path).as_deref()) {
+        println!("Error : {} -> {}", why, rom_path);
+        std::process::exit(2);
+    }
+    let settings = apply_rom_info(&mut emulator, rom_path, &database, &config, options);
+
+    (emulator, settings.instructions_per_frame)
+}
+
+// Runs the emulator as fast as possible, without rendering, the step running one
+// instruction. Returns the number of frames and of instructions. An instruction that can
+// not run ends the process.
+fn run_uncapped(emulator: &mut Emulator, instructions_per_frame: u32, length: RunLength, mut step: impl FnMut(&mut Emulator) -> Result<(), CpuError>) -> (u32, u64) {
+    let (max_frames, max_instructions) = match length {
+        RunLength::Frames(frames) => (frames, u64::MAX),
+        RunLength::Instructions(instructions) => (u32::MAX, instructions)
+    };
+
+    let mut frames = 0;
+    let mut instructions = 0;
+    while frames < max_frames && instructions < max_instructions {
+        let count = (instructions_per_frame as u64).min(max_instructions - instructions);
+        for _ in 0..count {
+            if let Err(why) = step(emulator) {
+                print_cpu_error(&why, emulator.get_pc());
+                std::process::exit(1);
+            }
+        }
+        instructions += count;
+        frames += 1;
+        emulator.update_cpu_timers();
+    }
+
+    (frames, instructions)
+}
+
+fn trace_diff(trace_a: &str, trace_b: &str) {
+    match tracer::diff(trace_a, trace_b) {
+        Ok(None) => println!("Traces are identical."),
+        Ok(Some((line, line_a, line_b))) => {
+            println!("Traces diverge at line {} :", line);
+            println!("< {}", line_a);
+            println!("> {}", line_b);
+            std::process::exit(1);
+        },
+        Err(why) => {
+            println!("Error : Unable to read the traces !");
+            println!("Why -> {:?}", why);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn resize_window(canvas: &mut Canvas<Window>, scale: u32, panel_visible: bool) {
+    let (width, height) = window_size(scale, panel_visible);
+    canvas.window_mut().set_size(width, height).unwrap();
+}
+
+// Window size for the pixel scale, with room for the debugger panel when it is visible.
+fn window_size(scale: u32, panel_visible: bool) -> (u32, u32) {
+    let width = CHIP8_WIDTH as u32 * scale;
+    let height = CHIP8_HEIGHT as u32 * scale;
+
+    if panel_visible {
+        (width + PANEL_WIDTH, height.max(MIN_PANEL_HEIGHT))
+    } else {
+        (width, height)
+    }
+}
+
+// Loads the ROM, or the given entry of a zip archive.
+fn load_rom(emulator: &mut Emulator, rom_path: &str, entry: Option<&str>) -> Result<(), RomError> {
+    emulator.load_rom_from_bytes(&rom_loader::read_rom_entry(rom_path, entry)?)
+}
+
+fn run_rom(canvas: &mut Canvas<Window>, event_pump: &mut EventPump, sound_system: &SoundSystem, choice: &RomChoice, options: &Options, database: &RomDatabase, config: &Config) -> Result<SessionEnd, RomError> {
+    let rom_path = choice.path.as_str();
     let mut emulator = Emulator::new();
-    emulator.load_rom(rom_path);
+    load_rom(&mut emulator, rom_path, choice.entry.as_deref())?;
+
+    let mut settings = apply_rom_info(&mut emulator, rom_path, database, config, options);
+    canvas.window_mut().set_title(&settings.window_title).unwrap();
+    resize_window(canvas, settings.scale, false);
+
+    for (start, end, kind) in &options.watchpoints {
+        emulator.add_watchpoint(*start, *end, *kind);
+    }
+
+    if options.access_log_path.is_some() {
+        emulator.enable_access_log();
+    }
+
+    if options.profile || options.profile_path.is_some() {
+        emulator.enable_profiler();
+    }
+
+    if options.coverage_path.is_some() || options.coverage_image_path.is_some() {
+        emulator.enable_coverage();
+    }
+
+    if let Some(path) = &options.trace_path {
+        match Tracer::create(path, options.trace_format, options.trace_filter.clone()) {
+            Ok(tracer) => emulator.set_tracer(tracer),
+            Err(why) => {
+                println!("Error : Unable to create the trace file !");
+                println!("Why -> {:?}", why);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut debugger = Debugger::new();
+
+    let mut watcher = if options.watch && rom_path != "-" {Some(RomWatcher::new(rom_path))} else {None};
+
+    let mut gdb_stub = options.gdb_port.map(|port| {
+        println!("Waiting for a GDB connection on 127.0.0.1:{} ...", port);
+        GdbStub::listen(port).unwrap_or_else(|why| {
+            println!("Error : Unable to start the GDB server !");
+            println!("Why -> {:?}", why);
+            std::process::exit(1);
+        })
+    });
+
+    // The host plays alone until a guest joins, the guest joins before the first frame.
+    let mut netplay_host = None;
+    let mut netplay_session = None;
+    match &options.netplay {
+        Some(NetplayRole::Host(port)) => {
+            let keys = options.own_keys.unwrap_or(DEFAULT_HOST_KEYS);
+            match NetplayHost::listen(*port, keys, options.input_delay.unwrap_or(DEFAULT_INPUT_DELAY)) {
+                Ok(host) => {
+                    println!("Waiting for a second player on port {}, you own the keys {} ...", port, netplay::describe_keys(keys));
+                    netplay_host = Some(host);
+                },
+                Err(why) => {
+                    println!("Error : Unable to start the netplay server !");
+                    println!("Why -> {:?}", why);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some(NetplayRole::Join(address)) => {
+            if options.input_delay.is_some() {
+                println!("Warning : The input delay is set by the host, --input-delay is ignored !");
+            }
+
+            println!("Joining {} ...", address);
+            match netplay::join(address, options.own_keys.unwrap_or(DEFAULT_GUEST_KEYS), &mut emulator) {
+                Ok(session) => {
+                    print_netplay_start(&session);
+                    netplay_session = Some(session);
+                },
+                Err(why) => {
+                    println!("Error : Unable to join {} -> {}", address, why);
+                    std::process::exit(1);
+                }
+            }
+        },
+        None => ()
+    }
+
+    let mut current_path = rom_path.to_string();
+    let mut current_entry = choice.entry.clone();
+    let mut fast_forward = false;
+    // Each frame lasts FRAME_DURATION * slow_motion : 1, 2 or 4 for 100%, 50% or 25% speed.
+    let mut slow_motion = 1;
+    let mut advance_frame = false;
+    let mut status = None;
+    let mut refresh_title = false;
 
     let mut running = true;
+    let mut session_end = SessionEnd::Back;
 
-    let mut div_cycles: u8 = 0;
     while running {
-        emulator.run_instruction();
-        div_cycles += 1;
+        let frame_start = Instant::now();
 
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} => {
                     running = false;
+                    session_end = SessionEnd::Quit;
                 },
-                Event::KeyDown { keycode, .. } => {
-                    match keycode.unwrap() {
+                // the other player would go out of sync.
+                Event::KeyDown { keycode: Some(Keycode::F2 | Keycode::F3 | Keycode::F4 | Keycode::F5 | Keycode::F7 | Keycode::Tab), .. } |
+                Event::DropFile { .. } if netplay_session.is_some() => {
+                    println!("Warning : Reset, reload, pause, slow motion and fast forward are disabled during netplay !");
+                },
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    match keycode {
                         Keycode::Escape => {
                             running = false;
                         },
-                        Keycode::Num1 => {
-                            emulator.key(0x1, true);
-                        },
-                        Keycode::Num2 => {
-                            emulator.key(0x2, true);
+                        Keycode::F1 => {
+                            debugger.toggle_visible();
+                            resize_window(canvas, settings.scale, debugger.is_visible());
                         },
-                        Keycode::Num3 => {
-                            emulator.key(0x3, true);
+                        Keycode::F2 => {
+                            emulator.reset();
                         },
-                        Keycode::Num4 => {
-                            emulator.key(0xC, true);
+                        Keycode::F3 if current_path == "-" => {
+                            println!("Warning : The ROM was read from standard input, it can not be reloaded !");
+                            emulator.reset();
                         },
-                        Keycode::Q => {
-                            emulator.key(0x4, true);
+                        Keycode::F3 => {
+                            match load_rom(&mut emulator, &current_path, current_entry.as_deref()) {
+                                Ok(()) => {
+                                    settings = apply_rom_info(&mut emulator, &current_path, database, config, options);
+                                    resize_window(canvas, settings.scale, debugger.is_visible());
+                                    refresh_title = true;
+                                },
+                                Err(why) => println!("Error : {} -> {}", why, current_path)
+                            }
                         },
-                        Keycode::W => {
-                            emulator.key(0x5, true);
+                        Keycode::F4 => {
+                            slow_motion = match slow_motion {
+                                1 => 2,
+                                2 => 4,
+                                _ => 1
+                            };
                         },
-                        Keycode::E => {
-                            emulator.key(0x6, true);
+                        Keycode::F5 => {
+                            debugger.toggle_pause();
                         },
-                        Keycode::R => {
-                            emulator.key(0xD, true);
+                        Keycode::F6 if debugger.is_paused() => {
+                            if let Err(why) = emulator.run_instruction() {
+                                print_cpu_error(&why, emulator.get_pc());
+                            }
+                            if let Some(hit) = emulator.take_watch_hit() {
+                                print_watch_hit(&hit);
+                            }
                         },
-                        Keycode::A => {
-                            emulator.key(0x7, true);
+                        Keycode::F7 if debugger.is_paused() => {
+                            advance_frame = true;
                         },
-                        Keycode::S => {
-                            emulator.key(0x8, true);
+                        Keycode::Tab => {
+                            fast_forward = true;
                         },
-                        Keycode::D => {
-                            emulator.key(0x9, true);
+                        Keycode::PageUp => {
+                            debugger.scroll_memory(-1, emulator.memory_size());
                         },
-                        Keycode::F => {
-                            emulator.key(0xE, true);
+                        Keycode::PageDown => {
+                            debugger.scroll_memory(1, emulator.memory_size());
                         },
-                        Keycode::Z => {
-                            emulator.key(0xA, true);
-                        },
-                        Keycode::X => {
-                            emulator.key(0x0, true);
-                        },
-                        Keycode::C => {
-                            emulator.key(0xB, true);
-                        },
-                        Keycode::V => {
-                            emulator.key(0xF, true);
-                        },
-                        _ => (),
+                        _ => {
+                            if let Some(key) = settings.keymap.lookup(keycode) {
+                                match netplay_session.as_mut() {
+                                    Some(session) => session.key(key, true),
+                                    None => emulator.key(key, true)
+                                }
+                            }
+                        }
                     }
                 },
-                Event::KeyUp {keycode, ..} => {
-                    match keycode.unwrap() {
-                        Keycode::Num1 => {
-                            emulator.key(0x1, false);
-                        },
-                        Keycode::Num2 => {
-                           emulator.key(0x2, false);
-                        },
-                        Keycode::Num3 => {
-                            emulator.key(0x3, false);
-                        },
-                        Keycode::Num4 => {
-                            emulator.key(0xC, false);
-                        },
-                        Keycode::Q => {
-                            emulator.key(0x4, false);
-                        },
-                        Keycode::W => {
-                            emulator.key(0x5, false);
-                        },
-                        Keycode::E => {
-                            emulator.key(0x6, false);
-                        },
-                        Keycode::R => {
-                            emulator.key(0xD, false);
-                        },
-                        Keycode::A => {
-                            emulator.key(0x7, false);
-                        },
-                        Keycode::S => {
-                            emulator.key(0x8, false);
-                        },
-                        Keycode::D => {
-                            emulator.key(0x9, false);
-                        },
-                        Keycode::F => {
-                            emulator.key(0xE, false);
-                        },
-                        Keycode::Z => {
-                            emulator.key(0xA, false);
-                        },
-                        Keycode::X => {
-                            emulator.key(0x0, false);
-                        },
-                        Keycode::C => {
-                            emulator.key(0xB, false);
-                        },
-                        Keycode::V => {
-                            emulator.key(0xF, false);
+                Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                    fast_forward = false;
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(key) = settings.keymap.lookup(keycode) {
+                        match netplay_session.as_mut() {
+                            Some(session) => session.key(key, false),
+                            None => emulator.key(key, false)
+                        }
+                    }
+                },
+                // an archive holding several ROMs is refused, there is no terminal to pick one.
+                Event::DropFile { filename, .. } => {
+                    match load_rom(&mut emulator, &filename, None) {
+                        Ok(()) => {
+                            if watcher.is_some() {
+                                watcher = Some(RomWatcher::new(&filename));
+                            }
+                            current_path = filename;
+                            current_entry = None;
+                            settings = apply_rom_info(&mut emulator, &current_path, database, config, options);
+                            resize_window(canvas, settings.scale, debugger.is_visible());
+                            refresh_title = true;
                         },
-                        _ => (),
+                        Err(why) => println!("Error : {} -> {}", why, filename)
                     }
                 },
                 _ => {}
             }
         }
-        
-        if emulator.has_drawn() {
-            for y in 0..CHIP8_HEIGHT {
-                for x in 0..CHIP8_WIDTH {
-                    if emulator.pixel_is_on_at(x as u8, y as u8) {
-                        canvas.set_draw_color(Color::RGB(255, 255, 255));
-                    } else {
-                        canvas.set_draw_color(Color::RGB(0, 0, 0));
+
+        if let Some(watcher) = watcher.as_mut().filter(|_| netplay_session.is_none()) {
+            if watcher.has_changed() {
+                match rom_loader::read_rom_entry(watcher.get_path(), current_entry.as_deref()).and_then(|rom| emulator.reload_rom(&rom, options.keep_state)) {
+                    Ok(true) => println!("ROM reloaded, state kept."),
+                    Ok(false) => {
+                        println!("ROM reloaded.");
+                        settings = apply_rom_info(&mut emulator, watcher.get_path(), database, config, options);
+                        resize_window(canvas, settings.scale, debugger.is_visible());
+                        refresh_title = true;
+                    },
+                    Err(why) => println!("Error : {} -> {}", why, watcher.get_path())
+                }
+            }
+        }
+
+        if let (Some(host), None) = (netplay_host.as_mut(), netplay_session.as_ref()) {
+            match host.accept(&emulator, settings.instructions_per_frame) {
+                Ok(Some(session)) => {
+                    print_netplay_start(&session);
+                    netplay_session = Some(session);
+                    fast_forward = false;
+                    slow_motion = 1;
+                    if debugger.is_paused() {
+                        debugger.toggle_pause();
+                    }
+                },
+                Ok(None) => (),
+                Err(why) => println!("Warning : A player could not join -> {}", why)
+            }
+        }
+
+        let mut netplay_running = false;
+        if let Some(session) = netplay_session.as_mut() {
+            match session.run_frame(&mut emulator) {
+                Ok(()) => {
+                    netplay_running = true;
+                    if settings.audio {
+                        sound_system.update(&emulator);
+                    }
+                },
+                Err(why) => {
+                    println!("Netplay stopped -> {}", why);
+                    netplay_session = None;
+                }
+            }
+        }
+
+        // during netplay, the session runs the frame.
+        let frames = if netplay_running {0} else if fast_forward {FAST_FORWARD_FRAMES} else {1};
+        for _ in 0..frames {
+            let mut halted = debugger.is_paused() && !advance_frame;
+            for _ in 0..settings.instructions_per_frame {
+                if let Some(stub) = gdb_stub.as_mut() {
+                    match stub.poll(&mut emulator) {
+                        Ok(true) => (),
+                        Ok(false) => running = false,
+                        Err(why) => {
+                            println!("GDB connection closed -> {:?}", why);
+                            gdb_stub = None;
+                        }
+                    }
+                }
+
+                halted |= gdb_stub.as_ref().is_some_and(|stub| !stub.is_running());
+                if halted || !running {
+                    break;
+                }
+
+                if let Err(why) = emulator.run_instruction() {
+                    print_cpu_error(&why, emulator.get_pc());
+                    match gdb_stub.as_mut() {
+                        Some(stub) => {
+                            if let Err(why) = stub.report_cpu_error() {
+                                println!("GDB connection closed -> {:?}", why);
+                                gdb_stub = None;
+                            }
+                        },
+                        None => debugger.pause()
                     }
+                    halted = true;
+                    break;
+                }
 
-                    let pixel = Rect::new(x as i32 * PIXEL_SCALE as i32, y as i32 * PIXEL_SCALE as i32, PIXEL_SCALE as u32, PIXEL_SCALE as u32);
-                    canvas.fill_rect(pixel).unwrap();
+                if let Some(hit) = emulator.take_watch_hit() {
+                    match gdb_stub.as_mut() {
+                        Some(stub) => {
+                            if let Err(why) = stub.report_watch_hit(hit) {
+                                println!("GDB connection closed -> {:?}", why);
+                                gdb_stub = None;
+                            }
+                        },
+                        None => {
+                            print_watch_hit(&hit);
+                            debugger.pause();
+                        }
+                    }
+                    halted = true;
+                    break;
                 }
             }
 
+            if !halted {
+                emulator.update_cpu_timers();
+                if settings.audio && !fast_forward {
+                    sound_system.update(&emulator);
+                }
+            }
+
+            if halted || !running {
+                break;
+            }
+        }
+        advance_frame = false;
+
+        let new_status = emulation_status(debugger.is_paused(), fast_forward, slow_motion);
+        if new_status != status || refresh_title {
+            let window_title = match &new_status {
+                Some(mode) => format!("{} [{}]", settings.window_title, mode),
+                None => settings.window_title.clone()
+            };
+            canvas.window_mut().set_title(&window_title).unwrap();
+            status = new_status;
+            refresh_title = false;
+        }
+
+        if emulator.has_drawn() || debugger.is_visible() {
+            draw_screen(canvas, &emulator, &settings.palette, settings.scale);
+
+            if debugger.is_visible() {
+                debugger.draw(canvas, &emulator, (CHIP8_WIDTH as u32 * settings.scale) as i32);
+            }
+
             emulator.reset_draw_flag();
 
             canvas.present();
         }
 
-        if div_cycles == TIMER_DIVISION_CLOCK {
-            emulator.update_cpu_timers();
-            div_cycles = 0;
+        if let Some(remaining) = (FRAME_DURATION * slow_motion).checked_sub(frame_start.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    save_reports(&emulator, options);
+
+    Ok(session_end)
+}
+
+// Applies the ROM database entry of the loaded ROM, if any.
+fn apply_rom_info(emulator: &mut Emulator, rom_path: &str, database: &RomDatabase, config: &Config, options: &Options) -> RomSettings {
+    let mut window_title = DEFAULT_WINDOW_TITLE.to_string();
+    let rom_info = emulator.get_rom_hash().and_then(|hash| database.lookup(hash));
+
+    if let Some(info) = rom_info {
+        if let Some(title) = &info.title {
+            match &info.author {
+                Some(author) => println!("ROM : {} by {}", title, author),
+                None => println!("ROM : {}", title)
+            }
+            window_title = format!("TLMC - {}", title);
+        }
+
+        if let Some(platform) = &info.platform {
+            if platform != "chip8" {
+                println!("Warning : This ROM targets {}, TLMC only emulates the original CHIP-8 instruction set !", platform);
+            }
+        }
+    }
+
+    let settings = effective_settings(rom_path, emulator.get_rom_hash(), rom_info, config, options);
+
+    match settings.get_quirks() {
+        Ok(quirks) => emulator.set_quirks(quirks),
+        Err(why) => {
+            println!("Warning : {}", why);
+            emulator.set_quirks(Quirks::default());
+        }
+    }
+
+    emulator.set_instruction_set(settings.get_instruction_set());
+
+    match settings.get_memory_map() {
+        Ok(map) => emulator.set_memory_map(map),
+        Err(why) => {
+            println!("Warning : {}", why);
+            emulator.set_memory_map(MemoryMap::default());
+        }
+    }
+
+    match settings.get_font() {
+        Ok((font, address)) => emulator.set_font(font, address),
+        Err(why) => {
+            println!("Warning : {}", why);
+            emulator.set_font(FontStyle::default(), 0x0);
         }
+    }
+
+    let mut palette = Palette {
+        foreground: Color::RGB(255, 255, 255),
+        background: Color::RGB(0, 0, 0)
+    };
+
+    if let Some((red, green, blue)) = settings.foreground.as_ref().and_then(|color| parse_color(color)) {
+        palette.foreground = Color::RGB(red, green, blue);
+    }
+
+    if let Some((red, green, blue)) = settings.background.as_ref().and_then(|color| parse_color(color)) {
+        palette.background = Color::RGB(red, green, blue);
+    }
+
+    let mut keymap = Keymap::new();
+    for (key, key_name) in &settings.keys {
+        if let Err(why) = keymap.bind(key, key_name) {
+            println!("Warning : {}", why);
+        }
+    }
 
-        std::thread::sleep(Duration::from_millis(CPU_CLOCK_DELAY));
+    if let Some(seed) = settings.seed {
+        emulator.set_seed(seed);
+    }
+
+    RomSettings {
+        window_title,
+        instructions_per_frame: settings.speed.unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME),
+        scale: settings.scale.unwrap_or(DEFAULT_PIXEL_SCALE),
+        palette,
+        keymap,
+        audio: settings.audio.unwrap_or(true)
+    }
+}
+
+// From the lowest to the highest priority : the configuration file, the ROM database,
+// the options of an Octo cartridge, the Octo options file, the ROM section of the
+// configuration file and the command line.
+fn effective_settings(rom_path: &str, sha1: Option<&str>, rom_info: Option<&RomInfo>, config: &Config, options: &Options) -> Settings {
+    let mut settings = config.global.clone();
+
+    if let Some(info) = rom_info {
+        settings.merge(&Settings::from(info));
+    }
+
+    if let Some(embedded) = rom_loader::embedded_settings(rom_path) {
+        settings.merge(&embedded);
+    }
+
+    if let Some(path) = octo_options::options_path(rom_path, options.octo_options_path.as_deref()) {
+        match OctoOptions::load(&path) {
+            Ok(octo_options) => {
+                if octo_options.screen_rotation.unwrap_or(0) != 0 {
+                    println!("Warning : Screen rotation is not supported, the screenRotation of {} is ignored !", path.display());
+                }
+                settings.merge(&octo_options.to_settings());
+            },
+            Err(why) => println!("Warning : {}", why)
+        }
+    }
+
+    if let Some(section) = config.rom_section(rom_path, sha1) {
+        settings.merge(section);
+    }
+
+    settings.merge(&options.settings);
+
+    settings
+}
+
+// Shown in the window title, None at normal speed.
+fn emulation_status(paused: bool, fast_forward: bool, slow_motion: u32) -> Option<String> {
+    if paused {
+        Some("PAUSED".to_string())
+    } else if fast_forward {
+        Some(format!("FAST x{}", FAST_FORWARD_FRAMES))
+    } else if slow_motion > 1 {
+        Some(format!("SLOW {}%", 100 / slow_motion))
+    } else {
+        None
+    }
+}
+
+fn save_reports(emulator: &Emulator, options: &Options) {
+    if let Some(path) = &options.access_log_path {
+        if let Err(why) = save_access_log(path, emulator) {
+            println!("Error : Unable to write the access log !");
+            println!("Why -> {:?}", why);
+        }
+    }
+
+    if let Some(coverage) = emulator.get_coverage() {
+        if let Some(path) = &options.coverage_path {
+            if let Err(why) = coverage.save_annotations(path) {
+                println!("Error : Unable to write the coverage map !");
+                println!("Why -> {:?}", why);
+            }
+        }
+
+        if let Some(path) = &options.coverage_image_path {
+            if let Err(why) = coverage.save_image(path) {
+                println!("Error : Unable to write the coverage image !");
+                println!("Why -> {:?}", why);
+            }
+        }
+    }
+
+    if let Some(profiler) = emulator.get_profiler() {
+        if options.profile {
+            profiler.print_report();
+        }
+
+        if let Some(path) = &options.profile_path {
+            if let Err(why) = profiler.save(path) {
+                println!("Error : Unable to write the profile !");
+                println!("Why -> {:?}", why);
+            }
+        }
+    }
+}
+
+// The ROM to load from a zip archive holding several, asked on the terminal. None for
+// any other file.
+fn archive_entry(rom_path: &str) -> Option<String> {
+    if !rom_loader::is_archive(rom_path) {
+        return None;
+    }
+
+    match rom_loader::list_archive_roms(rom_path) {
+        Ok(entries) if entries.len() > 1 => Some(pick_archive_entry(&entries)),
+        _ => None
+    }
+}
+
+fn pick_archive_entry(entries: &[String]) -> String {
+    println!("This archive holds several ROMs :");
+    for (i, entry) in entries.iter().enumerate() {
+        println!("  {}) {}", i + 1, entry);
+    }
+
+    loop {
+        print!("Pick a ROM [1-{}] : ", entries.len());
+        std::io::stdout().flush().unwrap();
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            std::process::exit(1);
+        }
+
+        match answer.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= entries.len() => return entries[n - 1].clone(),
+            _ => println!("Please enter a number between 1 and {}.", entries.len())
+        }
+    }
+}
+
+fn print_watch_hit(hit: &WatchHit) {
+    match hit.kind {
+        WatchKind::Fault => println!("Memory fault : access to 0x{:03X} by the instruction at 0x{:03X}", hit.addr, hit.pc),
+        _ => println!("Watchpoint : {:?} at 0x{:03X} (0x{:02X} -> 0x{:02X}) by the instruction at 0x{:03X}", hit.kind, hit.addr, hit.old_value, hit.new_value, hit.pc)
+    }
+}
+
+fn print_netplay_start(session: &NetplaySession) {
+    println!("Netplay started : you own the keys {}, the other player {}, with {} frames of input delay.", netplay::describe_keys(session.local_keys()), netplay::describe_keys(session.remote_keys()), session.input_delay());
+}
+
+fn print_cpu_error(why: &CpuError, pc: u16) {
+    println!("Error : {} at 0x{:03X}, the program is stopped !", why, pc);
+}
+
+fn save_access_log(path: &str, emulator: &Emulator) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "ADDR  READS  WRITES  LAST  PC")?;
+
+    if let Some(log) = emulator.get_access_log() {
+        for (addr, record) in log.iter().enumerate() {
+            if record.reads > 0 || record.writes > 0 {
+                writeln!(file, "{:03X}  {:5}  {:6}  {:02X}    {:03X}", addr, record.reads, record.writes, record.last_value, record.last_pc)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn draw_screen(canvas: &mut Canvas<Window>, emulator: &Emulator, palette: &Palette, scale: u32) {
+    // the window may be taller than the screen when the debugger panel is visible.
+    canvas.set_draw_color(palette.background);
+    canvas.clear();
+
+    for y in 0..CHIP8_HEIGHT {
+        for x in 0..CHIP8_WIDTH {
+            if emulator.pixel_is_on_at(x as u8, y as u8) {
+                canvas.set_draw_color(palette.foreground);
+            } else {
+                canvas.set_draw_color(palette.background);
+            }
+
+            let pixel = Rect::new(x as i32 * scale as i32, y as i32 * scale as i32, scale, scale);
+            canvas.fill_rect(pixel).unwrap();
+        }
     }
 }
 
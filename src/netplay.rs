@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::cpu::CpuError;
+use crate::emulator::Emulator;
+use crate::save_state::StateError;
+
+// Two players on one keypad, over TCP. Each player owns some of the keys and sends their
+// state for every frame, to be applied input_delay frames later. Both emulators run the
+// same frames with the same keys in lockstep, from the save state the host sent when the
+// guest joined, and compare checksums of their state every CHECKSUM_INTERVAL frames.
+const PROTOCOL_VERSION: u8 = 1;
+const CHECKSUM_INTERVAL: u32 = 60;
+// Longest wait for the other player.
+const TIMEOUT: Duration = Duration::from_secs(10);
+// A save state of 64 KiB of memory and the ROM fits.
+const MAX_MESSAGE_SIZE: usize = 0x40000;
+// The version, the keys and a SHA-1 in hexadecimal.
+const MAX_HELLO_SIZE: usize = 64;
+// Connections waiting for their hello, the oldest one is dropped beyond.
+const MAX_PENDING_GUESTS: usize = 4;
+
+pub const DEFAULT_INPUT_DELAY: u32 = 2;
+pub const MAX_INPUT_DELAY: u32 = 30;
+// Keys 0 to 7 for the host, 8 to F for the guest.
+pub const DEFAULT_HOST_KEYS: u16 = 0x00FF;
+pub const DEFAULT_GUEST_KEYS: u16 = 0xFF00;
+
+// Messages : a type byte, the payload size on 4 bytes, then the payload.
+// Guest : version, keys (2 bytes), SHA-1 of the ROM.
+const HELLO: u8 = 0x1;
+// Host : keys (2 bytes), input delay (4 bytes), instructions per frame (4 bytes), save state.
+const WELCOME: u8 = 0x2;
+// Host : the reason.
+const REFUSED: u8 = 0x3;
+// Frame (4 bytes), keys held (2 bytes).
+const INPUT: u8 = 0x4;
+// Frame (4 bytes), state checksum (8 bytes).
+const CHECKSUM: u8 = 0x5;
+const BYE: u8 = 0x6;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Refused(String),
+    Protocol(&'static str),
+    State(StateError),
+    Cpu(CpuError),
+    // The states differ at the end of this frame.
+    Desync(u32),
+    Left
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetplayError::Io(why) if why.kind() == ErrorKind::WouldBlock || why.kind() == ErrorKind::TimedOut => write!(f, "The other player did not answer for {} seconds", TIMEOUT.as_secs()),
+            NetplayError::Io(why) => write!(f, "Connection error ({})", why),
+            NetplayError::Refused(reason) => write!(f, "The host refused the connection ({})", reason),
+            NetplayError::Protocol(what) => write!(f, "Unexpected message from the other player ({})", what),
+            NetplayError::State(why) => write!(f, "Unable to load the state of the host ({})", why),
+            NetplayError::Cpu(why) => write!(f, "{}", why),
+            NetplayError::Desync(frame) => write!(f, "The emulators went out of sync at frame {}", frame),
+            NetplayError::Left => write!(f, "The other player left")
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<io::Error> for NetplayError {
+    fn from(why: io::Error) -> NetplayError {
+        match why.kind() {
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => NetplayError::Left,
+            _ => NetplayError::Io(why)
+        }
+    }
+}
+
+// Hexadecimal digits, e.g. "14" or "CD", as a mask with bit N for key N.
+pub fn parse_keys(keys: &str) -> Option<u16> {
+    if keys.is_empty() {
+        return None;
+    }
+
+    keys.chars().try_fold(0u16, |mask, key| key.to_digit(16).map(|key| mask | 1 << key))
+}
+
+pub fn describe_keys(mask: u16) -> String {
+    (0..16).filter(|key| mask & 1 << key != 0).map(|key| format!("{:X}", key)).collect()
+}
+
+// Waits for a guest while the host plays alone. The port is open on every network
+// interface, so that a player on another machine can join.
+pub struct NetplayHost {
+    listener: TcpListener,
+    keys: u16,
+    input_delay: u32,
+    pending: Vec<PendingGuest>
+}
+
+// A connection whose hello is not fully received yet. It is polled on each call to
+// accept, so that a client sending nothing does not stop the game of the host.
+struct PendingGuest {
+    stream: TcpStream,
+    received: Vec<u8>,
+    since: Instant
+}
+
+impl NetplayHost {
+    pub fn listen(port: u16, keys: u16, input_delay: u32) -> io::Result<NetplayHost> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(NetplayHost { listener, keys, input_delay, pending: Vec::new() })
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    // Returns None when no guest has sent its hello yet. Otherwise the guest gets the state
+    // of the emulator and both players start from it.
+    pub fn accept(&mut self, emulator: &Emulator, instructions_per_frame: u32) -> Result<Option<NetplaySession>, NetplayError> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    if self.pending.len() == MAX_PENDING_GUESTS {
+                        self.pending.remove(0);
+                    }
+                    self.pending.push(PendingGuest { stream, received: Vec::new(), since: Instant::now() });
+                },
+                Err(ref why) if why.kind() == ErrorKind::WouldBlock => break,
+                Err(why) => return Err(NetplayError::Io(why))
+            }
+        }
+
+        for index in 0..self.pending.len() {
+            match poll_hello(&mut self.pending[index]) {
+                Ok(None) => (),
+                Ok(Some(hello)) => {
+                    let guest = self.pending.remove(index);
+                    return self.welcome(guest.stream, &hello, emulator, instructions_per_frame).map(Some);
+                },
+                Err(why) => {
+                    self.pending.remove(index);
+                    return Err(why);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn welcome(&self, stream: TcpStream, hello: &[u8], emulator: &Emulator, instructions_per_frame: u32) -> Result<NetplaySession, NetplayError> {
+        let mut stream = configure(stream)?;
+
+        let guest_keys = u16::from_le_bytes([hello[1], hello[2]]);
+        let refusal = if hello[0] != PROTOCOL_VERSION {
+            Some(format!("netplay version {}, the host has version {}", hello[0], PROTOCOL_VERSION))
+        } else if Some(&hello[3..]) != emulator.get_rom_hash().map(|hash| hash.as_bytes()) {
+            Some("the ROMs differ".to_string())
+        } else if guest_keys & self.keys != 0 {
+            Some(format!("the host owns the keys {}", describe_keys(guest_keys & self.keys)))
+        } else {
+            None
+        };
+
+        if let Some(reason) = refusal {
+            write_message(&mut stream, REFUSED, reason.as_bytes())?;
+            return Err(NetplayError::Refused(reason));
+        }
+
+        let mut welcome = Vec::new();
+        welcome.extend_from_slice(&self.keys.to_le_bytes());
+        welcome.extend_from_slice(&self.input_delay.to_le_bytes());
+        welcome.extend_from_slice(&instructions_per_frame.to_le_bytes());
+        welcome.extend_from_slice(&emulator.save_state());
+        write_message(&mut stream, WELCOME, &welcome)?;
+
+        Ok(NetplaySession::new(stream, self.keys, guest_keys, self.input_delay, instructions_per_frame))
+    }
+}
+
+// Reads what the guest sent without waiting, and returns the payload of its hello once
+// it is complete.
+fn poll_hello(guest: &mut PendingGuest) -> Result<Option<Vec<u8>>, NetplayError> {
+    let mut buffer = [0u8; MAX_HELLO_SIZE];
+    loop {
+        if guest.received.len() >= 5 {
+            let len = u32::from_le_bytes(guest.received[1..5].try_into().unwrap()) as usize;
+            if guest.received[0] != HELLO || !(3..=MAX_HELLO_SIZE).contains(&len) {
+                return Err(NetplayError::Protocol("expected a hello"));
+            }
+            if guest.received.len() >= 5 + len {
+                return Ok(Some(guest.received[5..5 + len].to_vec()));
+            }
+        }
+
+        match guest.stream.read(&mut buffer) {
+            Ok(0) => return Err(NetplayError::Left),
+            Ok(count) => guest.received.extend_from_slice(&buffer[..count]),
+            Err(ref why) if why.kind() == ErrorKind::Interrupted => (),
+            Err(ref why) if why.kind() == ErrorKind::WouldBlock && guest.since.elapsed() < TIMEOUT => return Ok(None),
+            Err(why) if why.kind() == ErrorKind::WouldBlock => return Err(NetplayError::Io(io::Error::from(ErrorKind::TimedOut))),
+            Err(why) => return Err(why.into())
+        }
+    }
+}
+
+// Connects to a host and loads its state. The ROM must be the one of the host.
+pub fn join(address: &str, keys: u16, emulator: &mut Emulator) -> Result<NetplaySession, NetplayError> {
+    let address = address.to_socket_addrs()?.next().ok_or(NetplayError::Protocol("no address for the host"))?;
+    let mut stream = configure(TcpStream::connect_timeout(&address, TIMEOUT)?)?;
+
+    let mut hello = vec![PROTOCOL_VERSION];
+    hello.extend_from_slice(&keys.to_le_bytes());
+    hello.extend_from_slice(emulator.get_rom_hash().unwrap_or("").as_bytes());
+    write_message(&mut stream, HELLO, &hello)?;
+
+    match read_message(&mut stream)? {
+        (WELCOME, payload) if payload.len() >= 10 => {
+            let host_keys = u16::from_le_bytes([payload[0], payload[1]]);
+            let input_delay = u32::from_le_bytes(payload[2..6].try_into().unwrap());
+            let instructions_per_frame = u32::from_le_bytes(payload[6..10].try_into().unwrap());
+            if input_delay > MAX_INPUT_DELAY {
+                return Err(NetplayError::Protocol("input delay too long"));
+            }
+
+            emulator.load_state(&payload[10..]).map_err(NetplayError::State)?;
+
+            Ok(NetplaySession::new(stream, keys, host_keys, input_delay, instructions_per_frame))
+        },
+        (REFUSED, payload) => Err(NetplayError::Refused(String::from_utf8_lossy(&payload).into_owned())),
+        _ => Err(NetplayError::Protocol("expected a welcome"))
+    }
+}
+
+pub struct NetplaySession {
+    stream: TcpStream,
+    local_keys: u16,
+    remote_keys: u16,
+    input_delay: u32,
+    instructions_per_frame: u32,
+    // The next frame to run.
+    frame: u32,
+    // Keys held by the local player now, sent for frame + input_delay.
+    held: u16,
+    local_inputs: HashMap<u32, u16>,
+    remote_inputs: HashMap<u32, u16>,
+    local_checksums: HashMap<u32, u64>,
+    remote_checksums: HashMap<u32, u64>
+}
+
+impl NetplaySession {
+    fn new(stream: TcpStream, local_keys: u16, remote_keys: u16, input_delay: u32, instructions_per_frame: u32) -> NetplaySession {
+        // nobody holds a key during the first frames.
+        let no_input: HashMap<u32, u16> = (0..input_delay).map(|frame| (frame, 0x0)).collect();
+
+        NetplaySession {
+            stream,
+            local_keys,
+            remote_keys,
+            input_delay,
+            instructions_per_frame,
+            frame: 0,
+            held: 0x0,
+            local_inputs: no_input.clone(),
+            remote_inputs: no_input,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new()
+        }
+    }
+
+    pub fn local_keys(&self) -> u16 {
+        self.local_keys
+    }
+
+    pub fn remote_keys(&self) -> u16 {
+        self.remote_keys
+    }
+
+    pub fn input_delay(&self) -> u32 {
+        self.input_delay
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Keys of the other player are ignored.
+    pub fn key(&mut self, n: u8, is_down: bool) {
+        if is_down {
+            self.held |= 1 << n;
+        } else {
+            self.held &= !(1 << n);
+        }
+    }
+
+    // Sends the local keys, waits for the keys of the other player, then runs one frame.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> Result<(), NetplayError> {
+        let input_frame = self.frame + self.input_delay;
+        let held = self.held & self.local_keys;
+        self.local_inputs.insert(input_frame, held);
+
+        let mut input = input_frame.to_le_bytes().to_vec();
+        input.extend_from_slice(&held.to_le_bytes());
+        write_message(&mut self.stream, INPUT, &input)?;
+
+        let remote = loop {
+            match self.remote_inputs.remove(&self.frame) {
+                Some(remote) => break remote,
+                None => self.receive()?
+            }
+        };
+        let local = self.local_inputs.remove(&self.frame).unwrap_or(0x0);
+
+        let keys = (local & self.local_keys) | (remote & self.remote_keys);
+        for key in 0..16 {
+            emulator.key(key, keys & 1 << key != 0);
+        }
+
+        for _ in 0..self.instructions_per_frame {
+            emulator.run_instruction().map_err(NetplayError::Cpu)?;
+        }
+        emulator.update_cpu_timers();
+        self.frame += 1;
+
+        if self.frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            let checksum = emulator.state_checksum();
+            let mut message = self.frame.to_le_bytes().to_vec();
+            message.extend_from_slice(&checksum.to_le_bytes());
+            write_message(&mut self.stream, CHECKSUM, &message)?;
+
+            self.local_checksums.insert(self.frame, checksum);
+            self.compare_checksums(self.frame)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(), NetplayError> {
+        match read_message(&mut self.stream)? {
+            (INPUT, payload) if payload.len() == 6 => {
+                let frame = u32::from_le_bytes(payload[0..4].try_into().unwrap());
+                // the other player runs at most input_delay frames ahead.
+                if frame < self.frame || frame > self.frame + 2 * self.input_delay {
+                    return Err(NetplayError::Protocol("input for an unexpected frame"));
+                }
+                self.remote_inputs.insert(frame, u16::from_le_bytes([payload[4], payload[5]]));
+                Ok(())
+            },
+            (CHECKSUM, payload) if payload.len() == 12 => {
+                let frame = u32::from_le_bytes(payload[0..4].try_into().unwrap());
+                self.remote_checksums.insert(frame, u64::from_le_bytes(payload[4..12].try_into().unwrap()));
+                self.compare_checksums(frame)
+            },
+            (BYE, _) => Err(NetplayError::Left),
+            _ => Err(NetplayError::Protocol("unknown message"))
+        }
+    }
+
+    fn compare_checksums(&mut self, frame: u32) -> Result<(), NetplayError> {
+        if let (Some(local), Some(remote)) = (self.local_checksums.get(&frame), self.remote_checksums.get(&frame)) {
+            if local != remote {
+                return Err(NetplayError::Desync(frame));
+            }
+            self.local_checksums.remove(&frame);
+            self.remote_checksums.remove(&frame);
+        }
+
+        Ok(())
+    }
+}
+
+// Lets the other player know the session is over, rather than timing out.
+impl Drop for NetplaySession {
+    fn drop(&mut self) {
+        let _ = write_message(&mut self.stream, BYE, &[]);
+    }
+}
+
+fn configure(stream: TcpStream) -> io::Result<TcpStream> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    Ok(stream)
+}
+
+fn write_message(stream: &mut TcpStream, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(5 + payload.len());
+    message.push(kind);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(payload);
+
+    stream.write_all(&message)
+}
+
+fn read_message(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), NetplayError> {
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header)?;
+
+    let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(NetplayError::Protocol("message too large"));
+    }
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+
+    Ok((header[0], payload))
+}
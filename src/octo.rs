@@ -0,0 +1,663 @@
+use std::collections::HashMap;
+
+use crate::assembler::AsmError;
+
+const ROM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+
+const UNSUPPORTED: [&str; 14] = [
+    "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit",
+    "saveflags", "loadflags", "plane", "audio", "pitch", "bighex", "long"
+];
+
+// Compiles Octo source code, for the original CHIP-8 instruction set : labels, :const,
+// :alias, :unpack, :next, :org, :byte, :call, loop/again/while, if/then and
+// if/begin/else/end, with the <, >, <= and >= comparisons. Macros, :calc and the
+// SUPER-CHIP and XO-CHIP instructions are not supported.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap();
+        tokens.extend(code.split_whitespace().map(|text| Token { text, line: i + 1 }));
+    }
+
+    let mut compiler = Compiler {
+        tokens,
+        pos: 0,
+        memory: vec![0; MEMORY_SIZE],
+        here: ROM_START,
+        end: ROM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        branches: Vec::new()
+    };
+
+    compiler.compile()
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize
+}
+
+enum FixupKind {
+    // low 12 bits of an instruction.
+    Address,
+    // the two instructions of an :unpack.
+    Unpack
+}
+
+struct Fixup {
+    addr: usize,
+    label: String,
+    line: usize,
+    kind: FixupKind
+}
+
+struct Compiler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+    // start address and pending while jumps of each open loop.
+    loops: Vec<(usize, Vec<usize>)>,
+    // pending jump of each open if/begin.
+    branches: Vec<usize>
+}
+
+impl<'a> Compiler<'a> {
+    fn compile(&mut self) -> Result<Vec<u8>, AsmError> {
+        // the program starts at main : jump there unless it comes first.
+        let main_first = matches!(self.tokens.as_slice(), [colon, main, ..] if colon.text == ":" && main.text == "main");
+        if !main_first {
+            self.emit_fixup(0x1000, "main", FixupKind::Address)?;
+        }
+
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        let line = self.tokens.last().map_or(1, |token| token.line);
+        if !self.loops.is_empty() {
+            return Err(AsmError { line, message: "loop without again".to_string() });
+        }
+        if !self.branches.is_empty() {
+            return Err(AsmError { line, message: "begin without end".to_string() });
+        }
+
+        for fixup in &self.fixups {
+            let addr = *self.labels.get(&fixup.label).ok_or_else(|| AsmError { line: fixup.line, message: format!("Undefined label {}", fixup.label) })? as usize;
+            match fixup.kind {
+                FixupKind::Address => {
+                    self.memory[fixup.addr] |= (addr >> 8) as u8;
+                    self.memory[fixup.addr + 1] = addr as u8;
+                },
+                FixupKind::Unpack => {
+                    self.memory[fixup.addr + 1] |= (addr >> 8) as u8;
+                    self.memory[fixup.addr + 3] = addr as u8;
+                }
+            }
+        }
+
+        Ok(self.memory[ROM_START..self.end].to_vec())
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        let line = self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |token| token.line);
+        Err(AsmError { line, message })
+    }
+
+    fn next(&mut self) -> Result<&'a str, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.text)
+            },
+            None => self.error("Unexpected end of the program".to_string())
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected {}, found {}", expected, token));
+        }
+
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here >= MEMORY_SIZE {
+            return self.error("The program does not fit into memory".to_string());
+        }
+
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), AsmError> {
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte(opcode as u8)
+    }
+
+    // An instruction whose address is patched once the label is known.
+    fn emit_fixup(&mut self, opcode: u16, label: &str, kind: FixupKind) -> Result<(), AsmError> {
+        let line = self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |token| token.line);
+        self.fixups.push(Fixup { addr: self.here, label: label.to_string(), line, kind });
+
+        self.emit(opcode)
+    }
+
+    fn patch_jump(&mut self, addr: usize, target: usize) {
+        self.memory[addr] = 0x10 | (target >> 8) as u8;
+        self.memory[addr + 1] = target as u8;
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(token) {
+            return Some(*reg);
+        }
+
+        let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn expect_register(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        match self.register(token) {
+            Some(reg) => Ok(reg as u16),
+            None => self.error(format!("Expected a register, found {}", token))
+        }
+    }
+
+    fn number(&self, token: &str) -> Option<i32> {
+        if let Some(value) = self.constants.get(token) {
+            return Some(*value as i32);
+        }
+
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token)
+        };
+
+        let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i32::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+            i32::from_str_radix(binary, 2).ok()
+        } else {
+            digits.parse().ok()
+        }?;
+
+        Some(if negative {-value} else {value})
+    }
+
+    // A byte value, negative values wrapping around.
+    fn expect_byte(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        match self.number(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok((value & 0xFF) as u16),
+            Some(_) => self.error(format!("{} does not fit into a byte", token)),
+            None => self.error(format!("Expected a number, found {}", token))
+        }
+    }
+
+    // A 12-bit address : a number, a constant or a label, possibly defined later.
+    fn address_operand(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if let Some(value) = self.number(token) {
+            if !(0..MEMORY_SIZE as i32).contains(&value) {
+                return self.error(format!("{} is not an address", token));
+            }
+            return self.emit(opcode | value as u16);
+        }
+
+        match self.labels.get(token) {
+            Some(addr) => self.emit(opcode | addr),
+            None => self.emit_fixup(opcode, token, FixupKind::Address)
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+
+        if UNSUPPORTED.contains(&token) || token == "i" && self.peek() == Some(":=") && matches!(self.tokens.get(self.pos + 1).map(|token| token.text), Some("long") | Some("bighex")) {
+            return self.error(format!("{} is a SUPER-CHIP or XO-CHIP instruction, TLMC only runs CHIP-8 programs", token));
+        }
+
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.to_string(), self.here as u16).is_some() {
+                    return self.error(format!("Duplicate label {}", name));
+                }
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value_token = self.next()?;
+                let value = match self.number(value_token).or_else(|| self.labels.get(value_token).map(|addr| *addr as i32)) {
+                    Some(value) => value,
+                    None => return self.error(format!("Expected a number, found {}", value_token))
+                };
+                self.constants.insert(name.to_string(), value as u16);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.expect_register()?;
+                self.aliases.insert(name.to_string(), reg as u8);
+            },
+            ":unpack" => {
+                let nibble = self.next()?;
+                let nibble = match self.number(nibble) {
+                    Some(nibble) if (0..=0xF).contains(&nibble) => nibble as u16,
+                    _ => return self.error(format!("Expected a nibble, found {}", nibble))
+                };
+                let label = self.next()?;
+                match self.labels.get(label).copied() {
+                    Some(addr) => {
+                        self.emit(0x6000 | nibble << 4 | addr >> 8)?;
+                        self.emit(0x6100 | (addr & 0xFF))?;
+                    },
+                    None => {
+                        self.emit_fixup(0x6000 | nibble << 4, label, FixupKind::Unpack)?;
+                        self.emit(0x6100)?;
+                    }
+                }
+            },
+            ":next" => {
+                let name = self.next()?;
+                self.labels.insert(name.to_string(), (self.here + 1) as u16);
+            },
+            ":org" => {
+                let addr = self.next()?;
+                match self.number(addr) {
+                    Some(addr) if (ROM_START as i32..MEMORY_SIZE as i32).contains(&addr) => self.here = addr as usize,
+                    _ => return self.error(format!("Invalid :org address {}", addr))
+                }
+            },
+            ":byte" => {
+                let byte = self.expect_byte()?;
+                self.emit_byte(byte as u8)?;
+            },
+            ":call" => self.address_operand(0x2000)?,
+            ":breakpoint" => {
+                self.next()?;
+            },
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(0xF033 | x << 8)?;
+            },
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                if self.peek() == Some("-") {
+                    return self.error(format!("{} with a register range is an XO-CHIP instruction", token));
+                }
+                self.emit(if token == "save" {0xF055} else {0xF065} | x << 8)?;
+            },
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.next()?;
+                match self.number(n) {
+                    Some(n) if (1..=0xF).contains(&n) => self.emit(0xD000 | x << 8 | y << 4 | n as u16)?,
+                    Some(0) => return self.error("sprite 0 is a SUPER-CHIP instruction".to_string()),
+                    _ => return self.error(format!("Invalid sprite height {}", n))
+                }
+            },
+            "jump" => self.address_operand(0x1000)?,
+            "jump0" => self.address_operand(0xB000)?,
+            "native" => self.address_operand(0x0000)?,
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "again" => {
+                let (start, exits) = match self.loops.pop() {
+                    Some(open_loop) => open_loop,
+                    None => return self.error("again without loop".to_string())
+                };
+                self.emit(0x1000 | start as u16)?;
+                for exit in exits {
+                    self.patch_jump(exit, self.here);
+                }
+            },
+            "while" => {
+                if self.loops.is_empty() {
+                    return self.error("while outside of a loop".to_string());
+                }
+                self.condition(true)?;
+                let exit = self.here;
+                self.emit(0x1000)?;
+                self.loops.last_mut().unwrap().1.push(exit);
+            },
+            "if" => {
+                // "then" skips the next statement when the condition is false,
+                // "begin" jumps to else/end when it is false.
+                let start = self.pos;
+                while let Some(token) = self.peek() {
+                    if token == "then" || token == "begin" {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let keyword = self.peek().unwrap_or("");
+                let end = self.pos;
+                self.pos = start;
+
+                match keyword {
+                    "then" => {
+                        self.condition(false)?;
+                        self.pos = end + 1;
+                    },
+                    "begin" => {
+                        self.condition(true)?;
+                        self.pos = end + 1;
+                        self.branches.push(self.here);
+                        self.emit(0x1000)?;
+                    },
+                    _ => return self.error("if without then or begin".to_string())
+                }
+            },
+            "else" => {
+                let branch = match self.branches.pop() {
+                    Some(branch) => branch,
+                    None => return self.error("else without begin".to_string())
+                };
+                let jump = self.here;
+                self.emit(0x1000)?;
+                self.patch_jump(branch, self.here);
+                self.branches.push(jump);
+            },
+            "end" => {
+                match self.branches.pop() {
+                    Some(branch) => self.patch_jump(branch, self.here),
+                    None => return self.error("end without begin".to_string())
+                }
+            },
+            "i" => {
+                let operator = self.next()?;
+                match operator {
+                    ":=" => {
+                        if self.peek() == Some("hex") {
+                            self.pos += 1;
+                            let x = self.expect_register()?;
+                            self.emit(0xF029 | x << 8)?;
+                        } else {
+                            self.address_operand(0xA000)?;
+                        }
+                    },
+                    "+=" => {
+                        let x = self.expect_register()?;
+                        self.emit(0xF01E | x << 8)?;
+                    },
+                    _ => return self.error(format!("Unknown operator i {}", operator))
+                }
+            },
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                self.emit(if token == "delay" {0xF015} else {0xF018} | x << 8)?;
+            },
+            _ => {
+                if let Some(x) = self.register(token) {
+                    return self.assignment(x as u16);
+                }
+
+                match self.number(token) {
+                    Some(value) if (-128..=255).contains(&value) => self.emit_byte(value as u8)?,
+                    Some(_) => return self.error(format!("{} does not fit into a byte", token)),
+                    // a label alone calls it.
+                    None if token.starts_with(':') => return self.error(format!("Unsupported directive {}", token)),
+                    None => {
+                        self.pos -= 1;
+                        self.address_operand(0x2000)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), AsmError> {
+        let operator = self.next()?;
+        let operand = self.next()?;
+        let y = self.register(operand).map(|reg| reg as u16);
+
+        let opcode = match (operator, y) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            (":=", None) => match operand {
+                "random" => 0xC000 | x << 8 | self.expect_byte()?,
+                "key" => 0xF00A | x << 8,
+                "delay" => 0xF007 | x << 8,
+                _ => {
+                    self.pos -= 1;
+                    0x6000 | x << 8 | self.expect_byte()?
+                }
+            },
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("+=", None) => {
+                self.pos -= 1;
+                0x7000 | x << 8 | self.expect_byte()?
+            },
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            ("-=", None) => {
+                self.pos -= 1;
+                0x7000 | x << 8 | (0x100 - self.expect_byte()?) & 0xFF
+            },
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            _ => return self.error(format!("Invalid operation v{:X} {} {}", x, operator, operand))
+        };
+
+        self.emit(opcode)
+    }
+
+    // Emits the instructions that skip the next one when the condition is equal to skip_when.
+    fn condition(&mut self, skip_when: bool) -> Result<(), AsmError> {
+        let x = self.expect_register()?;
+        let operator = self.next()?;
+
+        let (x, equal, y, nn) = match operator {
+            "key" | "-key" => {
+                let skip_if_down = (operator == "key") == skip_when;
+                return self.emit(if skip_if_down {0xE09E} else {0xE0A1} | x << 8);
+            },
+            "==" | "!=" => {
+                let operand = self.next()?;
+                let y = self.register(operand).map(|reg| reg as u16);
+                if y.is_none() {
+                    self.pos -= 1;
+                }
+                let nn = if y.is_none() {self.expect_byte()?} else {0x0};
+                (x, operator == "==", y, nn)
+            },
+            "<" | ">" | "<=" | ">=" => {
+                let operand = self.next()?;
+                let y = self.register(operand).map(|reg| reg as u16);
+
+                // VF := NOT borrow of x - y (for < and >=) or of y - x (for > and <=).
+                let x_minus_y = operator == "<" || operator == ">=";
+                match y {
+                    Some(y) => {
+                        let (first, second) = if x_minus_y {(x, y)} else {(y, x)};
+                        self.emit(0x8F00 | first << 4)?;
+                        self.emit(0x8F05 | second << 4)?;
+                    },
+                    None => {
+                        self.pos -= 1;
+                        let nn = self.expect_byte()?;
+                        self.emit(0x6F00 | nn)?;
+                        self.emit(if x_minus_y {0x8F07} else {0x8F05} | x << 4)?;
+                    }
+                }
+
+                // < and > hold when VF is 0, <= and >= when VF is 1.
+                let holds_when_zero = operator == "<" || operator == ">";
+                (0xF, holds_when_zero, None, 0x0)
+            },
+            _ => return self.error(format!("Unknown comparison {}", operator))
+        };
+
+        // SE skips when equal, SNE when not equal.
+        let skip_if_equal = equal == skip_when;
+        let opcode = match (y, skip_if_equal) {
+            (Some(y), true) => 0x5000 | x << 8 | y << 4,
+            (Some(y), false) => 0x9000 | x << 8 | y << 4,
+            (None, true) => 0x3000 | x << 8 | nn,
+            (None, false) => 0x4000 | x << 8 | nn
+        };
+
+        self.emit(opcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+    use crate::emulator::Emulator;
+    use crate::quirks::Quirks;
+
+    fn assert_compiles_to(source: &str, expected: &[u8]) {
+        let rom = compile(source).unwrap_or_else(|why| panic!("{} : {}", why.line, why.message));
+        assert_eq!(rom, expected, "{}", source);
+    }
+
+    #[test]
+    fn if_then_and_begin_else_end() {
+        let source = "
+            : main
+                v0 := 5
+                if v0 == 5 then v1 := 2
+                if v0 != v1 begin
+                    v2 := 1
+                else
+                    v2 := 2
+                end
+        ";
+        assert_compiles_to(source, &[
+            0x60, 0x05,
+            0x40, 0x05, 0x61, 0x02,
+            0x90, 0x10, 0x12, 0x0E,
+            0x62, 0x01, 0x12, 0x10,
+            0x62, 0x02
+        ]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = "
+            : main
+                v0 := 0
+                loop
+                    v0 += 1
+                    while v0 != 10
+                again
+        ";
+        assert_compiles_to(source, &[0x60, 0x00, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn unpack_and_next() {
+        let source = "
+            : main
+                :unpack 0xA data
+                :next counter
+                v2 := 0
+                i := counter
+                save v0
+                loop again
+            : data
+                0xF0 0x90
+        ";
+        assert_compiles_to(source, &[
+            0x60, 0xA2, 0x61, 0x0C,
+            0x62, 0x00,
+            0xA2, 0x05, 0xF0, 0x55,
+            0x12, 0x0A,
+            0xF0, 0x90
+        ]);
+    }
+
+    #[test]
+    fn main_is_jumped_to() {
+        assert_compiles_to(": data 0xFF : main i := data", &[0x12, 0x03, 0xFF, 0xA2, 0x02]);
+    }
+
+    // V2 is set when "vx <operator> vy" holds and V3 when "vx <operator> y" holds, run
+    // with Octo's quirks.
+    fn compare(x: u8, operator: &str, y: u8) -> (bool, bool) {
+        let source = format!("
+            : main
+                v0 := {x}
+                v1 := {y}
+                if v0 {operator} v1 then v2 := 1
+                if v0 {operator} {y} then v3 := 1
+                loop again
+        ");
+        let rom = compile(&source).unwrap();
+
+        let mut emulator = Emulator::new();
+        emulator.set_quirks(Quirks::preset("xochip").unwrap());
+        emulator.load_rom_from_bytes(&rom).unwrap();
+        for _ in 0..20 {
+            emulator.run_instruction().unwrap();
+        }
+
+        (emulator.get_register(0x2) == 1, emulator.get_register(0x3) == 1)
+    }
+
+    fn check_comparison(operator: &str, holds: fn(u8, u8) -> bool) {
+        for (x, y) in [(3, 5), (5, 3), (5, 5), (0, 0xFF), (0xFF, 0)] {
+            let expected = holds(x, y);
+            assert_eq!(compare(x, operator, y), (expected, expected), "{} {} {}", x, operator, y);
+        }
+    }
+
+    #[test]
+    fn less_than() {
+        check_comparison("<", |x, y| x < y);
+    }
+
+    #[test]
+    fn greater_than() {
+        check_comparison(">", |x, y| x > y);
+    }
+
+    #[test]
+    fn less_than_or_equal() {
+        check_comparison("<=", |x, y| x <= y);
+    }
+
+    #[test]
+    fn greater_than_or_equal() {
+        check_comparison(">=", |x, y| x >= y);
+    }
+
+    #[test]
+    fn equal_and_not_equal() {
+        check_comparison("==", |x, y| x == y);
+        check_comparison("!=", |x, y| x != y);
+    }
+}
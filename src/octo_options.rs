@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::{QuirkFlags, Settings};
+
+// Octo's per-program options, as stored in cartridges and in the options files of the
+// Octo ROM archive.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    pub tickrate: Option<u32>,
+    pub fill_color: Option<String>,
+    pub background_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub vf_order_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+    pub v_blank_quirks: Option<bool>,
+    pub screen_rotation: Option<u32>,
+    pub font_style: Option<String>
+}
+
+impl OctoOptions {
+    pub fn load(path: &Path) -> Result<OctoOptions, String> {
+        let content = fs::read_to_string(path).map_err(|why| format!("Unable to read the options file {} ({})", path.display(), why))?;
+
+        serde_json::from_str(&content).map_err(|why| format!("Unable to parse the options file {} ({})", path.display(), why))
+    }
+
+    pub fn to_settings(&self) -> Settings {
+        Settings {
+            speed: self.tickrate.filter(|tickrate| *tickrate > 0),
+            foreground: self.fill_color.clone(),
+            background: self.background_color.clone(),
+            font: self.font_style.as_deref().map(font_name),
+            quirk_flags: QuirkFlags {
+                vf_reset: self.logic_quirks,
+                shift: self.shift_quirks,
+                load_store: self.load_store_quirks,
+                jump: self.jump_quirks,
+                clip: self.clip_quirks,
+                display_wait: self.v_blank_quirks,
+                vf_order: self.vf_order_quirks
+            },
+            ..Settings::default()
+        }
+    }
+}
+
+// The TLMC font for an Octo font style. TLMC has no copy of Fish 'N' Chips, its programs
+// get the default font of Octo instead.
+fn font_name(font_style: &str) -> String {
+    match font_style {
+        "fish" => "octo",
+        font_style => font_style
+    }.to_string()
+}
+
+// The options file given on the command line, else the one next to the ROM : game.json
+// for game.ch8.
+pub fn options_path(rom_path: &str, explicit_path: Option<&str>) -> Option<PathBuf> {
+    if let Some(path) = explicit_path {
+        return Some(PathBuf::from(path));
+    }
+
+    if rom_path == "-" {
+        return None;
+    }
+
+    let path = Path::new(rom_path).with_extension("json");
+    path.is_file().then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::OctoOptions;
+    use crate::font::FontStyle;
+
+    #[test]
+    fn octo_font_styles_are_known() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fish.json");
+        let settings = OctoOptions::load(&path).unwrap().to_settings();
+        assert_eq!(settings.font.as_deref(), Some("octo"));
+        assert_eq!(settings.speed, Some(15));
+
+        for (font_style, font) in [("octo", FontStyle::Octo), ("vip", FontStyle::Vip), ("dream6800", FontStyle::Dream6800), ("eti660", FontStyle::Eti660), ("schip", FontStyle::Chip48), ("fish", FontStyle::Octo)] {
+            let options: OctoOptions = serde_json::from_str(&format!("{{\"fontStyle\": \"{}\"}}", font_style)).unwrap();
+            let name = options.to_settings().font.unwrap();
+            assert_eq!(FontStyle::from_name(&name), Some(font), "{}", font_style);
+        }
+    }
+}
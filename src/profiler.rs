@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::disassembler::{disassemble, opcode_family};
+use crate::instruction::{decode, Instruction, InstructionSet};
+
+const ADDRESS_SPACE: usize = 0x10000;
+const HOTSPOTS_COUNT: usize = 20;
+
+#[derive(Clone, Copy, Default)]
+struct SubroutineStats {
+    calls: u64,
+    // Instructions executed between the CALL and its RET, nested calls included.
+    instructions: u64
+}
+
+#[derive(Clone)]
+pub struct Profiler {
+    total: u64,
+    address_counts: Vec<u64>,
+    address_opcodes: Vec<u16>,
+    family_counts: HashMap<&'static str, u64>,
+    subroutines: HashMap<u16, SubroutineStats>,
+    call_stack: Vec<(u16, u64)>
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            total: 0,
+            address_counts: vec![0; ADDRESS_SPACE],
+            address_opcodes: vec![0; ADDRESS_SPACE],
+            family_counts: HashMap::new(),
+            subroutines: HashMap::new(),
+            call_stack: Vec::new()
+        }
+    }
+
+    // Called before each instruction is executed.
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        self.total += 1;
+        self.address_counts[pc as usize] += 1;
+        self.address_opcodes[pc as usize] = opcode;
+        *self.family_counts.entry(opcode_family(opcode)).or_insert(0) += 1;
+
+        match decode(opcode, InstructionSet::Schip) {
+            Ok(Instruction::Call(target)) => self.call_stack.push((target, self.total)),
+            Ok(Instruction::Ret) => {
+                if let Some((target, start)) = self.call_stack.pop() {
+                    let stats = self.subroutines.entry(target).or_default();
+                    stats.calls += 1;
+                    stats.instructions += self.total - start;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn hotspots(&self) -> Vec<(u16, u64)> {
+        let mut hotspots: Vec<(u16, u64)> = self.address_counts.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(addr, count)| (addr as u16, *count))
+            .collect();
+        hotspots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        hotspots
+    }
+
+    fn families(&self) -> Vec<(&'static str, u64)> {
+        let mut families: Vec<(&'static str, u64)> = self.family_counts.iter().map(|(family, count)| (*family, *count)).collect();
+        families.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        families
+    }
+
+    fn subroutines(&self) -> Vec<(u16, SubroutineStats)> {
+        let mut subroutines: Vec<(u16, SubroutineStats)> = self.subroutines.iter().map(|(addr, stats)| (*addr, *stats)).collect();
+        subroutines.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(&b.0)));
+
+        subroutines
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+
+        count as f64 * 100.0 / self.total as f64
+    }
+
+    pub fn print_report(&self) {
+        println!("Profile : {} instructions executed", self.total);
+
+        println!();
+        println!("Hotspots :");
+        println!("  ADDR       COUNT       %  OPCODE  MNEMONIC");
+        for (addr, count) in self.hotspots().iter().take(HOTSPOTS_COUNT) {
+            let opcode = self.address_opcodes[*addr as usize];
+            println!("  {:03X}  {:10}  {:5.1}%  {:04X}    {}", addr, count, self.percent(*count), opcode, disassemble(opcode));
+        }
+
+        println!();
+        println!("Opcode families :");
+        println!("  FAMILY       COUNT       %");
+        for (family, count) in self.families() {
+            println!("  {}    {:10}  {:5.1}%", family, count, self.percent(count));
+        }
+
+        println!();
+        println!("Subroutines :");
+        println!("  ADDR   CALLS  INSTRUCTIONS       %  PER CALL");
+        for (addr, stats) in self.subroutines() {
+            println!("  {:03X}  {:6}  {:12}  {:5.1}%  {:8}", addr, stats.calls, stats.instructions, self.percent(stats.instructions), stats.instructions / stats.calls);
+        }
+    }
+
+    // Exports the report as CSV or JSON, depending on the file extension.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if path.ends_with(".csv") {
+            writeln!(writer, "kind,key,count,percent,calls,opcode,mnemonic")?;
+            for (addr, count) in self.hotspots() {
+                let opcode = self.address_opcodes[addr as usize];
+                writeln!(writer, "address,{:03X},{},{:.3},,{:04X},{}", addr, count, self.percent(count), opcode, csv_field(&disassemble(opcode)))?;
+            }
+            for (family, count) in self.families() {
+                writeln!(writer, "family,{},{},{:.3},,,", family, count, self.percent(count))?;
+            }
+            for (addr, stats) in self.subroutines() {
+                writeln!(writer, "subroutine,{:03X},{},{:.3},{},,", addr, stats.instructions, self.percent(stats.instructions), stats.calls)?;
+            }
+        } else {
+            let hotspots: Vec<String> = self.hotspots().iter().map(|(addr, count)| {
+                let opcode = self.address_opcodes[*addr as usize];
+                format!("{{\"address\": {}, \"count\": {}, \"opcode\": {}, \"mnemonic\": \"{}\"}}", addr, count, opcode, disassemble(opcode))
+            }).collect();
+            let families: Vec<String> = self.families().iter().map(|(family, count)| {
+                format!("{{\"family\": \"{}\", \"count\": {}}}", family, count)
+            }).collect();
+            let subroutines: Vec<String> = self.subroutines().iter().map(|(addr, stats)| {
+                format!("{{\"address\": {}, \"calls\": {}, \"instructions\": {}}}", addr, stats.calls, stats.instructions)
+            }).collect();
+
+            writeln!(writer, "{{")?;
+            writeln!(writer, "  \"total\": {},", self.total)?;
+            writeln!(writer, "  \"hotspots\": [\n    {}\n  ],", hotspots.join(",\n    "))?;
+            writeln!(writer, "  \"families\": [\n    {}\n  ],", families.join(",\n    "))?;
+            writeln!(writer, "  \"subroutines\": [\n    {}\n  ]", subroutines.join(",\n    "))?;
+            writeln!(writer, "}}")?;
+        }
+
+        writer.flush()
+    }
+}
+
+// Quoted, as the mnemonics hold commas : "LD [I], V3".
+fn csv_field(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
@@ -0,0 +1,68 @@
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    // 8XY6 and 8XYE shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    // FX55 and FX65 leave I unchanged instead of incrementing it.
+    pub load_store: bool,
+    // BNNN jumps to NNN + VX (as BXNN) instead of NNN + V0.
+    pub jump: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip: bool,
+    // DXYN waits for the next frame before drawing.
+    pub display_wait: bool,
+    // 8XY4 to 8XYE write VF before VX, so VF holds the result instead of the flag when X is F.
+    pub vf_order: bool
+}
+
+// TLMC's historical behaviour, used when nothing else is known about the ROM.
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift: true,
+            load_store: true,
+            jump: false,
+            clip: false,
+            display_wait: false,
+            vf_order: true
+        }
+    }
+}
+
+impl Quirks {
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "chip8" => Some(Quirks {
+                vf_reset: true,
+                shift: false,
+                load_store: false,
+                jump: false,
+                clip: true,
+                display_wait: true,
+                vf_order: false
+            }),
+            "schip" => Some(Quirks {
+                vf_reset: false,
+                shift: true,
+                load_store: true,
+                jump: true,
+                clip: true,
+                display_wait: false,
+                vf_order: false
+            }),
+            "xochip" => Some(Quirks {
+                vf_reset: false,
+                shift: false,
+                load_store: false,
+                jump: false,
+                clip: false,
+                display_wait: false,
+                vf_order: false
+            }),
+            _ => None
+        }
+    }
+}
@@ -1,31 +1,337 @@
-const RAM_SIZE: usize = 0x1000;
+use crate::bus::Bus;
+use crate::instruction::{decode, DecodeError, Instruction, InstructionSet};
+use crate::instruction_cache::InstructionCache;
+use crate::save_state::{StateError, StateReader, StateWriter};
 
+pub const CLASSIC_MEMORY_SIZE: usize = 0x1000;
+pub const XO_MEMORY_SIZE: usize = 0x10000;
+// The interpreter area on the original machines, below the program.
+const INTERPRETER_END: u16 = 0x200;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Change,
+    // An access outside of memory, or a write to the protected interpreter area.
+    Fault
+}
+
+// What happens to accesses beyond the end of memory.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum OutOfRange {
+    // Reads return 0 and writes are dropped.
+    #[default]
+    Ignore,
+    // The address wraps around to the start of memory.
+    Wrap,
+    // Like Ignore, and the access is reported as a watch hit, as are the writes to the
+    // protected interpreter area.
+    Fault
+}
+
+impl OutOfRange {
+    pub fn from_name(name: &str) -> Option<OutOfRange> {
+        match name {
+            "ignore" => Some(OutOfRange::Ignore),
+            "wrap" => Some(OutOfRange::Wrap),
+            "fault" => Some(OutOfRange::Fault),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryMap {
+    // CLASSIC_MEMORY_SIZE or XO_MEMORY_SIZE.
+    pub size: usize,
+    pub out_of_range: OutOfRange,
+    // Writes of the program below 0x200 are dropped.
+    pub protect_interpreter: bool
+}
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        MemoryMap {
+            size: CLASSIC_MEMORY_SIZE,
+            out_of_range: OutOfRange::default(),
+            protect_interpreter: false
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Watchpoint {
+    start: u16,
+    end: u16,
+    kind: WatchKind
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub kind: WatchKind,
+    pub addr: u16,
+    pub pc: u16,
+    pub old_value: u8,
+    pub new_value: u8
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct AccessRecord {
+    pub reads: u32,
+    pub writes: u32,
+    pub last_value: u8,
+    pub last_pc: u16
+}
+
+#[derive(Clone)]
 pub struct Ram {
-    ram: [u8; RAM_SIZE]
+    ram: Vec<u8>,
+    map: MemoryMap,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    access_log: Option<Vec<AccessRecord>>,
+    access_pc: u16,
+    instruction_cache: InstructionCache
+}
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
+    }
 }
 
 impl Ram {
     pub fn new() -> Ram {
         Ram {
-            ram: [0x0; RAM_SIZE]
+            ram: vec![0x0; CLASSIC_MEMORY_SIZE],
+            map: MemoryMap::default(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            access_log: None,
+            access_pc: 0x0,
+            instruction_cache: InstructionCache::new(CLASSIC_MEMORY_SIZE)
         }
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
-        if addr > RAM_SIZE as u16 {
-            println!("Memory Error : READ command out of range !");
-            return 0x0;
+    // Zeroes the memory. Watchpoints and the access log are kept.
+    pub fn clear(&mut self) {
+        self.ram.fill(0x0);
+        self.watch_hit = None;
+        self.instruction_cache.clear();
+    }
+
+    // A new size zeroes the memory.
+    pub fn set_memory_map(&mut self, map: MemoryMap) {
+        if map.size != self.map.size {
+            self.ram = vec![0x0; map.size];
+            self.instruction_cache.resize(map.size);
+            if self.access_log.is_some() {
+                self.access_log = Some(vec![AccessRecord::default(); map.size]);
+            }
         }
 
-        return self.ram[addr as usize];
+        self.map = map;
+    }
+
+    pub fn get_memory_map(&self) -> MemoryMap {
+        self.map
+    }
+
+    pub fn size(&self) -> usize {
+        self.map.size
     }
 
-    pub fn write(&mut self, addr: u16, value: u8) {
-        if addr > RAM_SIZE as u16 {
-            println!("Memory Error : WRITE command out of range !");
+    // The memory map and the memory. The watchpoints and the access log are kept on load.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.u32(self.map.size as u32);
+        writer.u8(match self.map.out_of_range {
+            OutOfRange::Ignore => 0,
+            OutOfRange::Wrap => 1,
+            OutOfRange::Fault => 2
+        });
+        writer.bool(self.map.protect_interpreter);
+        writer.bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let size = reader.u32()? as usize;
+        if size != CLASSIC_MEMORY_SIZE && size != XO_MEMORY_SIZE {
+            return Err(StateError::Invalid("memory size"));
+        }
+        let out_of_range = match reader.u8()? {
+            0 => OutOfRange::Ignore,
+            1 => OutOfRange::Wrap,
+            2 => OutOfRange::Fault,
+            _ => return Err(StateError::Invalid("out of range policy"))
+        };
+        let protect_interpreter = reader.bool()?;
+        let memory = reader.bytes(size)?;
+
+        self.set_memory_map(MemoryMap { size, out_of_range, protect_interpreter });
+        self.ram.copy_from_slice(memory);
+        self.watch_hit = None;
+        self.instruction_cache.clear();
+
+        Ok(())
+    }
+
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.instruction_cache.set_enabled(enabled);
+    }
+
+    // Untracked access, for ROM loading and debugging tools. The interpreter area is
+    // writable and out of range accesses are not reported.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match self.index(addr) {
+            Some(index) => self.ram[index],
+            None => 0x0
+        }
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        if let Some(index) = self.index(addr) {
+            self.ram[index] = value;
+            self.instruction_cache.invalidate(index);
+        }
+    }
+
+    fn index(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        if addr < self.map.size {
+            return Some(addr);
+        }
+
+        match self.map.out_of_range {
+            OutOfRange::Wrap => Some(addr % self.map.size),
+            _ => None
+        }
+    }
+
+    fn report_fault(&mut self, addr: u16, old_value: u8, new_value: u8) {
+        if self.watch_hit.is_none() {
+            self.watch_hit = Some(WatchHit {
+                kind: WatchKind::Fault,
+                addr,
+                pc: self.access_pc,
+                old_value,
+                new_value
+            });
+        }
+    }
+
+    // PC of the instruction being executed, reported by watch hits and the access log.
+    pub fn set_access_pc(&mut self, pc: u16) {
+        self.access_pc = pc;
+    }
+
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        let watchpoint = Watchpoint { start, end, kind };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.watchpoints.retain(|watchpoint| *watchpoint != Watchpoint { start, end, kind });
+    }
+
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    pub fn enable_access_log(&mut self) {
+        if self.access_log.is_none() {
+            self.access_log = Some(vec![AccessRecord::default(); self.map.size]);
+        }
+    }
+
+    pub fn get_access_log(&self) -> Option<&[AccessRecord]> {
+        self.access_log.as_deref()
+    }
+
+    fn track(&mut self, addr: u16, kind: WatchKind, old_value: u8, new_value: u8) {
+        if let Some(record) = self.access_log.as_mut().and_then(|log| log.get_mut(addr as usize)) {
+            match kind {
+                WatchKind::Read => record.reads += 1,
+                WatchKind::Fault => (),
+                _ => record.writes += 1
+            }
+            record.last_value = new_value;
+            record.last_pc = self.access_pc;
+        }
+
+        // Only the first hit of an instruction is kept.
+        if self.watch_hit.is_some() {
+            return;
+        }
+
+        for watchpoint in &self.watchpoints {
+            if addr < watchpoint.start || addr > watchpoint.end {
+                continue;
+            }
+
+            let hit_kind = match (watchpoint.kind, kind) {
+                (WatchKind::Read, WatchKind::Read) => WatchKind::Read,
+                (WatchKind::Write, WatchKind::Write) => WatchKind::Write,
+                (WatchKind::Change, WatchKind::Write) if old_value != new_value => WatchKind::Change,
+                _ => continue
+            };
+
+            self.watch_hit = Some(WatchHit {
+                kind: hit_kind,
+                addr,
+                pc: self.access_pc,
+                old_value,
+                new_value
+            });
             return;
         }
+    }
+}
+
+// Reads and writes made by the running program, seen by watchpoints and the access log.
+impl Bus for Ram {
+    fn fetch(&self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        if self.index(addr).is_none() && self.map.out_of_range == OutOfRange::Fault {
+            self.report_fault(addr, 0x0, 0x0);
+        }
+
+        let value = self.peek(addr);
+        self.track(addr, WatchKind::Read, value, value);
 
-        self.ram[addr as usize] = value;
+        value
     }
-}
\ No newline at end of file
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let old_value = self.peek(addr);
+
+        let protected = self.map.protect_interpreter && addr < INTERPRETER_END;
+        let out_of_range = self.index(addr).is_none();
+        if protected || out_of_range {
+            if self.map.out_of_range == OutOfRange::Fault {
+                self.report_fault(addr, old_value, value);
+            }
+            return;
+        }
+
+        self.poke(addr, value);
+        self.track(addr, WatchKind::Write, old_value, value);
+    }
+
+    fn fetch_instruction(&mut self, addr: u16, instruction_set: InstructionSet) -> Result<Instruction, DecodeError> {
+        self.instruction_cache.set_instruction_set(instruction_set);
+        if let Some(instruction) = self.instruction_cache.get(addr) {
+            return Ok(instruction);
+        }
+
+        let instruction = decode((self.peek(addr) as u16) << 8 | self.peek(addr.wrapping_add(1)) as u16, instruction_set)?;
+        self.instruction_cache.insert(addr, instruction);
+
+        Ok(instruction)
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+static BUNDLED_DATABASE: &str = include_str!("../roms/database.toml");
+
+#[derive(Deserialize, Clone, Default, Debug)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Option<String>,
+    pub instructions_per_frame: Option<u32>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    // Hexadecimal key -> SDL key name, e.g. "5" = "Up".
+    #[serde(default)]
+    pub keys: HashMap<String, String>
+}
+
+#[derive(Deserialize)]
+struct RomEntry {
+    sha1: String,
+    #[serde(flatten)]
+    info: RomInfo
+}
+
+#[derive(Deserialize)]
+struct DatabaseFile {
+    #[serde(default, rename = "rom")]
+    roms: Vec<RomEntry>
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>
+}
+
+impl RomDatabase {
+    // The bundled database, with the entries of the user database file added or overridden.
+    pub fn load() -> RomDatabase {
+        let mut database = RomDatabase {
+            roms: HashMap::new()
+        };
+
+        if let Err(why) = database.merge(BUNDLED_DATABASE) {
+            println!("Error : The bundled ROM database is invalid !");
+            println!("Why -> {}", why);
+        }
+
+        if let Some(path) = user_database_path() {
+            if let Ok(content) = fs::read_to_string(&path) {
+                if let Err(why) = database.merge(&content) {
+                    println!("Error : Unable to parse the ROM database {} !", path.display());
+                    println!("Why -> {}", why);
+                }
+            }
+        }
+
+        database
+    }
+
+    fn merge(&mut self, content: &str) -> Result<(), toml::de::Error> {
+        let file: DatabaseFile = toml::from_str(content)?;
+
+        for entry in file.roms {
+            self.roms.insert(entry.sha1.to_ascii_lowercase(), entry.info);
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+}
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// $XDG_CONFIG_HOME/tlmc, or ~/.config/tlmc.
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("tlmc")),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("tlmc"))
+    }
+}
+
+fn user_database_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("roms.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{rom_hash, DatabaseFile, RomDatabase, BUNDLED_DATABASE};
+    use crate::assembler::assemble;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn bundled_database_is_valid() {
+        let file: DatabaseFile = toml::from_str(BUNDLED_DATABASE).unwrap();
+        assert!(!file.roms.is_empty());
+
+        for entry in &file.roms {
+            assert!(entry.sha1.len() == 40 && entry.sha1.chars().all(|c| c.is_ascii_hexdigit()), "{}", entry.sha1);
+            for preset in [&entry.info.platform, &entry.info.quirks].into_iter().flatten() {
+                assert!(Quirks::preset(preset).is_some(), "{} : {}", entry.sha1, preset);
+            }
+        }
+    }
+
+    #[test]
+    fn bundled_roms_are_found() {
+        let mut database = RomDatabase { roms: Default::default() };
+        database.merge(BUNDLED_DATABASE).unwrap();
+
+        let source = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/flags.asm")).unwrap();
+        let info = database.lookup(&rom_hash(&assemble(&source).unwrap())).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Flags test"));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fs::{self, File};
+use std::io::{self, Read, BufReader};
+use std::path::Path;
+use std::time::SystemTime;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::cartridge;
+use crate::config::Settings;
+use crate::emulator::RomError;
+use crate::octo;
+
+// Octo writes the comparisons as "vf := vx  vf -= vy" and tests VF, so the flag must
+// win over the result (no vf_order quirk).
+const OCTO_QUIRKS: &str = "xochip";
+
+static ROM_EXTENSIONS: [&str; 7] = ["ch8", "c8", "sc8", "xo8", "sc", "rom", "bin"];
+
+// Reads a ROM file, a gzip compressed ROM (.gz), a zip archive holding a single
+// ROM (.zip), or standard input when the path is "-". Octo cartridges (.gif) and
+// Octo source files (.8o) are compiled.
+pub fn read_rom(rom_path: &str) -> Result<Vec<u8>, RomError> {
+    if rom_path == "-" {
+        return read_all(io::stdin().lock()).and_then(|data| compile_octo(data, false));
+    }
+
+    let extension = file_extension(rom_path);
+    let rom_file = File::open(rom_path).map_err(RomError::Open)?;
+
+    let data = match extension.as_deref() {
+        Some("gz") => read_all(GzDecoder::new(BufReader::new(rom_file))),
+        Some("zip") => {
+            let mut entries = list_archive_roms(rom_path)?;
+            match entries.len() {
+                0 => Err(RomError::NoRomInArchive),
+                1 => read_rom_from_archive(rom_path, &entries.remove(0)),
+                _ => Err(RomError::MultipleRomsInArchive(entries))
+            }
+        },
+        _ => read_all(BufReader::new(rom_file))
+    }?;
+
+    compile_octo(data, extension.as_deref() == Some("8o"))
+}
+
+// The given entry of a zip archive, or the ROM file itself when there is no entry.
+pub fn read_rom_entry(rom_path: &str, entry: Option<&str>) -> Result<Vec<u8>, RomError> {
+    match entry {
+        Some(entry) => read_rom_from_archive(rom_path, entry),
+        None => read_rom(rom_path)
+    }
+}
+
+// The settings of a program compiled from Octo : Octo's quirks, then the options
+// embedded in a cartridge. None for any other file.
+pub fn embedded_settings(rom_path: &str) -> Option<Settings> {
+    if rom_path == "-" {
+        return None;
+    }
+
+    let octo_profile = Settings {
+        quirks: Some(OCTO_QUIRKS.to_string()),
+        ..Settings::default()
+    };
+
+    if file_extension(rom_path).as_deref() == Some("8o") {
+        return Some(octo_profile);
+    }
+
+    let data = fs::read(rom_path).ok()?;
+    if !cartridge::is_cartridge(&data) {
+        return None;
+    }
+
+    let mut settings = octo_profile;
+    if let Ok(cartridge) = cartridge::decode(&data) {
+        settings.merge(&cartridge.options.to_settings());
+    }
+
+    Some(settings)
+}
+
+// Whether the launcher should list the file : a known ROM extension, a gzip/zip archive,
+// an Octo cartridge or an Octo source file.
+pub fn is_rom_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            let extension = extension.to_ascii_lowercase();
+            ROM_EXTENSIONS.contains(&extension.as_str()) || ["gz", "zip", "gif", "8o"].contains(&extension.as_str())
+        },
+        None => false
+    }
+}
+
+pub fn is_archive(path: &str) -> bool {
+    file_extension(path).as_deref() == Some("zip")
+}
+
+fn file_extension(path: &str) -> Option<String> {
+    Path::new(path).extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase())
+}
+
+// Cartridges are recognised by their GIF signature, source files by their extension.
+fn compile_octo(data: Vec<u8>, is_source: bool) -> Result<Vec<u8>, RomError> {
+    if cartridge::is_cartridge(&data) {
+        let cartridge = cartridge::decode(&data).map_err(RomError::Cartridge)?;
+        return octo::compile(&cartridge.program).map_err(RomError::Source);
+    }
+
+    if is_source {
+        let source = String::from_utf8_lossy(&data);
+        return octo::compile(&source).map_err(RomError::Source);
+    }
+
+    Ok(data)
+}
+
+// Names of the archive entries that look like ROMs.
+pub fn list_archive_roms(archive_path: &str) -> Result<Vec<String>, RomError> {
+    let archive = ZipArchive::new(File::open(archive_path).map_err(RomError::Open)?).map_err(RomError::Archive)?;
+
+    let mut entries: Vec<String> = archive.file_names()
+        .filter(|name| !name.ends_with('/'))
+        .filter(|name| match Path::new(name).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()),
+            None => true
+        })
+        .map(|name| name.to_string())
+        .collect();
+    entries.sort();
+
+    Ok(entries)
+}
+
+pub fn read_rom_from_archive(archive_path: &str, entry: &str) -> Result<Vec<u8>, RomError> {
+    let mut archive = ZipArchive::new(File::open(archive_path).map_err(RomError::Open)?).map_err(RomError::Archive)?;
+    let rom_file = archive.by_name(entry).map_err(RomError::Archive)?;
+
+    read_all(rom_file)
+}
+
+pub(crate) fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>, RomError> {
+    let mut rom_buffer = Vec::new();
+    reader.read_to_end(&mut rom_buffer).map_err(RomError::Read)?;
+
+    Ok(rom_buffer)
+}
+
+// Notices when a ROM file is rewritten, by polling its modification time.
+pub struct RomWatcher {
+    path: String,
+    modified: Option<SystemTime>
+}
+
+impl RomWatcher {
+    pub fn new(path: &str) -> RomWatcher {
+        RomWatcher {
+            path: path.to_string(),
+            modified: modified_time(path)
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn has_changed(&mut self) -> bool {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{embedded_settings, read_rom};
+    use crate::emulator::{Emulator, RomError};
+    use crate::quirks::Quirks;
+
+    static CARTRIDGE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/cartridge.gif");
+
+    #[test]
+    fn octo_programs_run_with_octo_quirks() {
+        let settings = embedded_settings("game.8o").unwrap();
+        assert_eq!(settings.get_quirks().unwrap(), Quirks::preset("xochip").unwrap());
+        assert!(embedded_settings("game.ch8").is_none());
+
+        // the options of the cartridge apply over them.
+        let settings = embedded_settings(CARTRIDGE_PATH).unwrap();
+        let quirks = settings.get_quirks().unwrap();
+        assert_eq!(quirks, Quirks { load_store: true, ..Quirks::preset("xochip").unwrap() });
+        assert_eq!(settings.speed, Some(20));
+
+        // "if v0 < v1 then v2 := 1" with v0 = 3 and v1 = 5.
+        let mut emulator = Emulator::new();
+        emulator.set_quirks(quirks);
+        emulator.load_rom_from_bytes(&read_rom(CARTRIDGE_PATH).unwrap()).unwrap();
+        for _ in 0..10 {
+            emulator.run_instruction().unwrap();
+        }
+        assert_eq!(emulator.get_register(0x2), 1);
+    }
+
+    #[test]
+    fn roms_are_loaded_from_readers() {
+        let mut emulator = Emulator::new();
+        emulator.load_rom_from_reader(Cursor::new([0x60, 0x2A])).unwrap();
+        emulator.run_instruction().unwrap();
+        assert_eq!(emulator.get_register(0x0), 0x2A);
+
+        assert!(matches!(emulator.load_rom_from_reader(Cursor::new([])), Err(RomError::Empty)));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+// Snapshots of the emulated machine : what the program can observe, so that two
+// emulators loading the same state run the same way. The debugging tools, the
+// watchpoints and the instruction cache are not part of it.
+const MAGIC: &[u8; 8] = b"TLMCSTAT";
+const STATE_VERSION: u8 = 2;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StateError {
+    NotAState,
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid(&'static str)
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "The data is not a TLMC save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "The save state version {} is not supported, expected {}", version, STATE_VERSION),
+            StateError::Truncated => write!(f, "The save state is truncated"),
+            StateError::Invalid(what) => write!(f, "The save state holds an invalid {}", what)
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Little-endian fields, after the magic and the version.
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut data = MAGIC.to_vec();
+        data.push(STATE_VERSION);
+
+        StateWriter { data }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Fixed size data, the reader knows its length.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Variable size data, preceded by its length.
+    pub fn block(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if !data.starts_with(MAGIC) {
+            return Err(StateError::NotAState);
+        }
+
+        let mut reader = StateReader { data, position: MAGIC.len() };
+        match reader.u8()? {
+            STATE_VERSION => Ok(reader),
+            version => Err(StateError::UnsupportedVersion(version))
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position.checked_add(len).filter(|end| *end <= self.data.len()).ok_or(StateError::Truncated)?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean"))
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, StateError> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+
+    pub fn block(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    // The whole state must have been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.position == self.data.len() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("trailing data"))
+        }
+    }
+}
+
+// FNV-1a, to compare two states without sending them.
+pub fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3))
+}
@@ -2,30 +2,42 @@ extern crate sdl2;
 
 use sdl2::mixer::{InitFlag, AUDIO_S16LSB, DEFAULT_CHANNELS, Music};
 
-pub struct SoundSystem<'a> {
-    beep_sound: Option<Music<'a>>
+use tlmc::emulator::Emulator;
+
+// Audio output of the frontend. The SDL mixer is global to the process, so there is a
+// single sound system, attached to the emulator on screen.
+pub struct SoundSystem {
+    beep_sound: Music<'static>
 }
 
-impl <'a> SoundSystem<'a> {
-    pub fn new() -> SoundSystem<'a> {
-        let mut sound_system = SoundSystem {
-            beep_sound: None
-        };
+impl Default for SoundSystem {
+    fn default() -> SoundSystem {
+        SoundSystem::new()
+    }
+}
 
+impl SoundSystem {
+    pub fn new() -> SoundSystem {
         sdl2::mixer::open_audio(44100, AUDIO_S16LSB, DEFAULT_CHANNELS, 1024).expect("Unable to open sdl2 mixer audio");
 
         sdl2::mixer::init(InitFlag::MP3).expect("Unable to init SDL mixer !");
 
         sdl2::mixer::allocate_channels(4);
 
-        sound_system.beep_sound = Some(Music::from_file("chip8_beep.mp3").expect("Unable to load beep sound !"));
+        let beep_sound = Music::from_file("chip8_beep.mp3").expect("Unable to load beep sound !");
 
         Music::set_volume(50);
 
-        sound_system
+        SoundSystem {
+            beep_sound
+        }
     }
 
-    pub fn play_beep_sound(&self) {
-        self.beep_sound.as_ref().unwrap().play(0).unwrap();
+    // Called after the timers of the emulator are updated : the beep starts when the
+    // sound timer counts down to 1.
+    pub fn update(&self, emulator: &Emulator) {
+        if emulator.get_sound_timer() == 0x1 {
+            self.beep_sound.play(0).unwrap();
+        }
     }
 }
@@ -0,0 +1,88 @@
+extern crate sdl2;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use tlmc::font::CHIP48_FONT;
+
+const TEXT_SCALE: i32 = 2;
+const GLYPH_WIDTH: i32 = 4;
+const GLYPH_HEIGHT: i32 = 5;
+pub const CHAR_WIDTH: i32 = (GLYPH_WIDTH + 1) * TEXT_SCALE;
+pub const LINE_HEIGHT: i32 = (GLYPH_HEIGHT + 1) * TEXT_SCALE;
+
+// Glyphs for the characters the CHIP-48 font does not cover, in the same 4x5 format.
+static EXTRA_GLYPHS: [(char, [u8; 5]); 39] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0x70, 0x20, 0x20, 0x20, 0x70]),
+    ('J', [0x10, 0x10, 0x10, 0x90, 0x60]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xE0, 0x90, 0xE0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xB0, 0x70]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0x60]),
+    ('V', [0x90, 0x90, 0x90, 0x60, 0x60]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('+', [0x00, 0x40, 0xE0, 0x40, 0x00]),
+    ('=', [0x00, 0xF0, 0x00, 0xF0, 0x00]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    ('[', [0x60, 0x40, 0x40, 0x40, 0x60]),
+    (']', [0x60, 0x20, 0x20, 0x20, 0x60]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    ('/', [0x10, 0x20, 0x20, 0x40, 0x80]),
+    ('#', [0x50, 0xF0, 0x50, 0xF0, 0x50]),
+    ('*', [0x00, 0xA0, 0x40, 0xA0, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0xF0]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('&', [0x40, 0xA0, 0x40, 0xA0, 0x50]),
+];
+
+fn glyph(c: char) -> Option<[u8; 5]> {
+    let c = c.to_ascii_uppercase();
+
+    if let Some(digit) = c.to_digit(16) {
+        let start = digit as usize * 5;
+        let mut rows = [0x0; 5];
+        rows.copy_from_slice(&CHIP48_FONT[start..start + 5]);
+        return Some(rows);
+    }
+
+    EXTRA_GLYPHS.iter().find(|(glyph_char, _)| *glyph_char == c).map(|(_, rows)| *rows)
+}
+
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, color: Color) {
+    canvas.set_draw_color(color);
+
+    for (i, c) in text.chars().enumerate() {
+        if let Some(rows) = glyph(c) {
+            let char_x = x + i as i32 * CHAR_WIDTH;
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0x80 >> col) != 0 {
+                        let dot = Rect::new(char_x + col * TEXT_SCALE, y + row as i32 * TEXT_SCALE, TEXT_SCALE as u32, TEXT_SCALE as u32);
+                        canvas.fill_rect(dot).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write, ErrorKind};
+
+use crate::cpu::Cpu;
+use crate::disassembler::disassemble;
+
+const BINARY_MAGIC: &[u8; 8] = b"TLMCTRC1";
+const BINARY_RECORD_SIZE: usize = 30;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Binary
+}
+
+#[derive(Clone, Default)]
+pub struct TraceFilter {
+    // Inclusive PC range.
+    pub range: Option<(u16, u16)>,
+    // Opcode classes, given by the high nibble of the opcode.
+    pub classes: Option<Vec<u8>>
+}
+
+impl TraceFilter {
+    fn accepts(&self, pc: u16, opcode: u16) -> bool {
+        if let Some((start, end)) = self.range {
+            if pc < start || pc > end {
+                return false;
+            }
+        }
+
+        match &self.classes {
+            Some(classes) => classes.contains(&((opcode >> 12) as u8)),
+            None => true
+        }
+    }
+}
+
+pub struct Tracer {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    cycle: u64
+}
+
+impl Tracer {
+    pub fn create(path: &str, format: TraceFormat, filter: TraceFilter) -> io::Result<Tracer> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+        }
+
+        Ok(Tracer {
+            writer,
+            format,
+            filter,
+            cycle: 0
+        })
+    }
+
+    // Called after each executed instruction, with the PC and opcode it was fetched from.
+    pub fn record(&mut self, pc: u16, opcode: u16, cpu: &Cpu) -> io::Result<()> {
+        self.cycle += 1;
+
+        if !self.filter.accepts(pc, opcode) {
+            return Ok(());
+        }
+
+        let mut registers = [0x0; 16];
+        for (i, reg) in registers.iter_mut().enumerate() {
+            *reg = cpu.get_register(i as u8);
+        }
+
+        match self.format {
+            TraceFormat::Text => {
+                writeln!(self.writer, "{}", format_line(self.cycle, pc, opcode, &registers, cpu.get_index_reg()))
+            },
+            TraceFormat::Binary => {
+                let mut record = Vec::with_capacity(BINARY_RECORD_SIZE);
+                record.extend_from_slice(&self.cycle.to_le_bytes());
+                record.extend_from_slice(&pc.to_le_bytes());
+                record.extend_from_slice(&opcode.to_le_bytes());
+                record.extend_from_slice(&registers);
+                record.extend_from_slice(&cpu.get_index_reg().to_le_bytes());
+                self.writer.write_all(&record)
+            }
+        }
+    }
+}
+
+fn format_line(cycle: u64, pc: u16, opcode: u16, registers: &[u8], index_reg: u16) -> String {
+    let registers: Vec<String> = registers.iter().map(|reg| format!("{:02X}", reg)).collect();
+
+    format!("{:10} {:03X} {:04X} {:<20} {} I:{:04X}", cycle, pc, opcode, disassemble(opcode), registers.join(" "), index_reg)
+}
+
+// Reads back a text or binary trace as text lines.
+fn read_trace(path: &str) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let records = data[BINARY_MAGIC.len()..].chunks(BINARY_RECORD_SIZE);
+        return records.map(|record| {
+            if record.len() < BINARY_RECORD_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "truncated binary trace"));
+            }
+
+            let cycle = u64::from_le_bytes(record[0..8].try_into().unwrap());
+            let pc = u16::from_le_bytes([record[8], record[9]]);
+            let opcode = u16::from_le_bytes([record[10], record[11]]);
+            let index_reg = u16::from_le_bytes([record[28], record[29]]);
+
+            Ok(format_line(cycle, pc, opcode, &record[12..28], index_reg))
+        }).collect();
+    }
+
+    reader.lines().collect()
+}
+
+// Returns the first line number (1-based) where the traces differ, with both lines.
+// A trace that ends early differs on the first missing line.
+pub fn diff(path_a: &str, path_b: &str) -> io::Result<Option<(usize, String, String)>> {
+    let trace_a = read_trace(path_a)?;
+    let trace_b = read_trace(path_b)?;
+
+    for i in 0..trace_a.len().max(trace_b.len()) {
+        let line_a = trace_a.get(i).map_or("<end of trace>", |line| line.as_str());
+        let line_b = trace_b.get(i).map_or("<end of trace>", |line| line.as_str());
+
+        if line_a != line_b {
+            return Ok(Some((i + 1, line_a.to_string(), line_b.to_string())));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,48 @@
+// Helpers shared by the integration tests and the benches, which each use a part of them.
+#![allow(dead_code)]
+
+use std::fs;
+
+use tlmc::assembler::assemble;
+use tlmc::emulator::Emulator;
+
+pub const INSTRUCTIONS_PER_FRAME: u32 = 15;
+const WIDTH: u8 = 64;
+const HEIGHT: u8 = 32;
+
+// (frame, key, is_down)
+pub type KeyEvent = (u32, u8, bool);
+
+// Assembles the source file, the path being relative to the crate.
+pub fn load_rom(path: &str) -> Vec<u8> {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path);
+    let source = fs::read_to_string(&path).unwrap_or_else(|why| panic!("Unable to read {} ({})", path, why));
+
+    assemble(&source).unwrap_or_else(|why| panic!("Unable to assemble {} ({})", path, why))
+}
+
+// Runs the frames at the usual speed, pressing and releasing the keys on the frames of
+// the events, counted from 0.
+pub fn run_frames(emulator: &mut Emulator, frames: u32, key_events: &[KeyEvent]) {
+    for frame in 0..frames {
+        for (_, key, is_down) in key_events.iter().filter(|(event_frame, _, _)| *event_frame == frame) {
+            emulator.key(*key, *is_down);
+        }
+        for _ in 0..INSTRUCTIONS_PER_FRAME {
+            emulator.run_instruction().unwrap_or_else(|why| panic!("{} at 0x{:03X}", why, emulator.get_pc()));
+        }
+        emulator.update_cpu_timers();
+    }
+}
+
+// The screen in the format of tlmc test, # for the pixels on.
+pub fn screen(emulator: &Emulator) -> String {
+    let mut screen = String::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            screen.push(if emulator.pixel_is_on_at(x, y) {'#'} else {'.'});
+        }
+        screen.push('\n');
+    }
+    screen
+}
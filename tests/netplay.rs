@@ -0,0 +1,199 @@
+// Two emulators playing together over localhost, each one on its own thread as two
+// instances of TLMC would.
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tlmc::assembler::assemble;
+use tlmc::emulator::Emulator;
+use tlmc::font::{FontStyle, MAX_FONT_ADDRESS};
+use tlmc::netplay::{self, NetplayError, NetplayHost, NetplaySession, DEFAULT_GUEST_KEYS, DEFAULT_HOST_KEYS};
+use tlmc::save_state::StateError;
+
+mod common;
+use common::{run_frames, screen, KeyEvent, INSTRUCTIONS_PER_FRAME};
+
+type Joined = Result<NetplaySession, NetplayError>;
+
+fn load_rom(name: &str) -> Vec<u8> {
+    common::load_rom(&format!("tests/roms/{}.asm", name))
+}
+
+fn emulator(rom: &[u8], seed: u64) -> Emulator {
+    let mut emulator = Emulator::new();
+    emulator.set_seed(seed);
+    emulator.load_rom_from_bytes(rom).unwrap();
+    emulator
+}
+
+// The host waits for the guest, which joins from another thread.
+fn connect(host_emulator: &mut Emulator, guest_emulator: Emulator, guest_keys: u16, input_delay: u32) -> (Joined, thread::JoinHandle<(Emulator, Joined)>) {
+    let mut host = NetplayHost::listen(0, DEFAULT_HOST_KEYS, input_delay).unwrap();
+    connect_to(&mut host, host_emulator, guest_emulator, guest_keys)
+}
+
+fn connect_to(host: &mut NetplayHost, host_emulator: &mut Emulator, guest_emulator: Emulator, guest_keys: u16) -> (Joined, thread::JoinHandle<(Emulator, Joined)>) {
+    let address = format!("127.0.0.1:{}", host.port().unwrap());
+
+    let guest = thread::spawn(move || {
+        let mut guest_emulator = guest_emulator;
+        let session = netplay::join(&address, guest_keys, &mut guest_emulator);
+        (guest_emulator, session)
+    });
+
+    loop {
+        match host.accept(host_emulator, INSTRUCTIONS_PER_FRAME) {
+            Ok(None) => thread::yield_now(),
+            Ok(Some(session)) => return (Ok(session), guest),
+            Err(why) => return (Err(why), guest)
+        }
+    }
+}
+
+fn play(emulator: &mut Emulator, session: &mut NetplaySession, frames: u32, key_events: &[KeyEvent]) -> Result<(), NetplayError> {
+    for frame in 0..frames {
+        for (_, key, is_down) in key_events.iter().filter(|(event_frame, _, _)| *event_frame == frame) {
+            session.key(*key, *is_down);
+        }
+        session.run_frame(emulator)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn players_share_the_keypad() {
+    let rom = load_rom("keypad");
+    let mut host_emulator = emulator(&rom, 1);
+    run_frames(&mut host_emulator, 5, &[]);
+    // a different seed, the state of the host replaces it.
+    let guest_emulator = emulator(&rom, 2);
+
+    let (host_session, guest) = connect(&mut host_emulator, guest_emulator, DEFAULT_GUEST_KEYS, 3);
+    let mut host_session = host_session.unwrap();
+    let (mut guest_emulator, guest_session) = guest.join().unwrap();
+    let mut guest_session = guest_session.unwrap();
+    assert_eq!(guest_emulator.state_checksum(), host_emulator.state_checksum());
+
+    // the host owns key 5 and the guest key C. The key C of the host is ignored.
+    // Both sessions stay open until both players are done.
+    let guest = thread::spawn(move || {
+        play(&mut guest_emulator, &mut guest_session, 180, &[(30, 0xC, true), (40, 0xC, false)]).unwrap();
+        (guest_emulator, guest_session)
+    });
+    play(&mut host_emulator, &mut host_session, 180, &[(10, 0x5, true), (20, 0x5, false), (15, 0xC, true)]).unwrap();
+    let (guest_emulator, _guest_session) = guest.join().unwrap();
+
+    assert_eq!(host_session.frame(), 180);
+    assert_eq!(guest_emulator.state_checksum(), host_emulator.state_checksum());
+
+    let expected = fs::read_to_string(format!("{}/tests/roms/keypad.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    assert_eq!(screen(&host_emulator), expected);
+}
+
+#[test]
+fn checksums_detect_a_desync() {
+    let rom = load_rom("keypad");
+    let mut host_emulator = emulator(&rom, 1);
+
+    let (host_session, guest) = connect(&mut host_emulator, emulator(&rom, 1), DEFAULT_GUEST_KEYS, 0);
+    let mut host_session = host_session.unwrap();
+    let (mut guest_emulator, guest_session) = guest.join().unwrap();
+    let mut guest_session = guest_session.unwrap();
+
+    guest_emulator.write_memory(0xF00, 0x42);
+
+    let guest = thread::spawn(move || play(&mut guest_emulator, &mut guest_session, 120, &[]));
+    let host_result = play(&mut host_emulator, &mut host_session, 120, &[]);
+    drop(host_session);
+    let guest_result = guest.join().unwrap();
+
+    let results = [host_result, guest_result];
+    assert!(results.iter().any(|result| matches!(result, Err(NetplayError::Desync(60)))), "{:?}", results);
+    assert!(results.iter().all(|result| matches!(result, Err(NetplayError::Desync(60)) | Err(NetplayError::Left))), "{:?}", results);
+}
+
+#[test]
+fn host_refuses_another_rom_and_its_own_keys() {
+    let mut host_emulator = emulator(&load_rom("keypad"), 1);
+
+    let (host_session, guest) = connect(&mut host_emulator, emulator(&load_rom("flags"), 1), DEFAULT_GUEST_KEYS, 2);
+    assert!(matches!(host_session, Err(NetplayError::Refused(_))));
+    assert!(matches!(guest.join().unwrap().1, Err(NetplayError::Refused(reason)) if reason == "the ROMs differ"));
+
+    let (host_session, guest) = connect(&mut host_emulator, emulator(&load_rom("keypad"), 1), 0x0030, 2);
+    assert!(matches!(host_session, Err(NetplayError::Refused(_))));
+    assert!(matches!(guest.join().unwrap().1, Err(NetplayError::Refused(reason)) if reason == "the host owns the keys 45"));
+}
+
+#[test]
+fn silent_connections_do_not_stop_the_host() {
+    let rom = load_rom("keypad");
+    let mut host_emulator = emulator(&rom, 1);
+    let mut host = NetplayHost::listen(0, DEFAULT_HOST_KEYS, 2).unwrap();
+
+    // a client that never sends its hello, and another one that sends half of it.
+    let _silent = TcpStream::connect(("127.0.0.1", host.port().unwrap())).unwrap();
+    let mut partial = TcpStream::connect(("127.0.0.1", host.port().unwrap())).unwrap();
+    partial.write_all(&[0x1, 43, 0]).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..10 {
+        assert!(host.accept(&host_emulator, INSTRUCTIONS_PER_FRAME).unwrap().is_none());
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let (host_session, guest) = connect_to(&mut host, &mut host_emulator, emulator(&rom, 1), DEFAULT_GUEST_KEYS);
+    assert!(host_session.is_ok());
+    assert!(guest.join().unwrap().1.is_ok());
+}
+
+#[test]
+fn save_state_resumes_the_same_program() {
+    let rom = assemble("loop:\n    CALL draw\n    JP loop\ndraw:\n    RND V0, 0x1F\n    RND V1, 0x0F\n    LD F, V0\n    DRW V0, V1, 5\n    LD I, 0x300\n    LD B, V0\n    RET\n").unwrap();
+    let mut original = emulator(&rom, 7);
+    run_frames(&mut original, 1, &[]);
+
+    let mut restored = emulator(&load_rom("flags"), 8);
+    restored.load_state(&original.save_state()).unwrap();
+    assert_eq!(restored.get_rom_hash(), original.get_rom_hash());
+
+    run_frames(&mut original, 30, &[]);
+    run_frames(&mut restored, 30, &[]);
+    assert_eq!(restored.save_state(), original.save_state());
+    assert_eq!(screen(&restored), screen(&original));
+
+    // a state that can not be loaded leaves the emulator as it was.
+    let state = original.save_state();
+    let checksum = restored.state_checksum();
+    assert_eq!(restored.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+    assert_eq!(restored.load_state(b"not a state"), Err(StateError::NotAState));
+    assert_eq!(restored.state_checksum(), checksum);
+}
+
+#[test]
+fn save_state_refuses_a_bad_font_address() {
+    let rom = load_rom("flags");
+    let mut original = emulator(&rom, 1);
+    original.set_font(FontStyle::Octo, 0x50);
+    let state = original.save_state();
+
+    // the address follows the magic, the version, the ROM and the font name, with their lengths.
+    let offset = 9 + 4 + rom.len() + 4 + FontStyle::Octo.name().len();
+    assert_eq!(state[offset..offset + 2], [0x50, 0x00]);
+
+    let mut restored = emulator(&rom, 1);
+    let checksum = restored.state_checksum();
+    for address in [0xFFFF, MAX_FONT_ADDRESS + 1, 0x60] {
+        let mut state = state.clone();
+        state[offset..offset + 2].copy_from_slice(&address.to_le_bytes());
+        assert_eq!(restored.load_state(&state), Err(StateError::Invalid("font address")));
+        assert_eq!(restored.state_checksum(), checksum);
+    }
+
+    restored.load_state(&state).unwrap();
+    restored.set_font(FontStyle::Vip, MAX_FONT_ADDRESS);
+    assert_eq!(restored.read_memory(MAX_FONT_ADDRESS), FontStyle::Vip.glyphs()[0]);
+}
@@ -0,0 +1,63 @@
+// Runs the test ROMs of tests/roms, assembled from their source, and compares the screen
+// with the golden dump next to them, in the format of tlmc test. Set TLMC_BLESS=1 to
+// write the dumps from the current screens instead.
+use std::env;
+use std::fs;
+
+use tlmc::emulator::Emulator;
+use tlmc::quirks::Quirks;
+
+mod common;
+use common::{load_rom, run_frames, screen, KeyEvent};
+
+const FRAMES: u32 = 120;
+
+fn rom_path(file_name: &str) -> String {
+    format!("{}/tests/roms/{}", env!("CARGO_MANIFEST_DIR"), file_name)
+}
+
+fn run_rom(name: &str, quirks: Quirks, key_events: &[KeyEvent]) -> String {
+    let mut emulator = Emulator::new();
+    emulator.set_seed(0);
+    emulator.set_quirks(quirks);
+    emulator.load_rom_from_bytes(&load_rom(&format!("tests/roms/{}.asm", name))).unwrap();
+
+    run_frames(&mut emulator, FRAMES, key_events);
+    screen(&emulator)
+}
+
+fn check_screen(golden_name: &str, screen: &str) {
+    let path = rom_path(&format!("{}.txt", golden_name));
+
+    if env::var_os("TLMC_BLESS").is_some() {
+        fs::write(&path, screen).unwrap_or_else(|why| panic!("Unable to write {} ({})", path, why));
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|why| panic!("Unable to read {} ({})", path, why));
+    assert!(expected.lines().map(|line| line.trim_end()).eq(screen.lines()), "The screen differs from {} :\n{}", path, screen);
+}
+
+#[test]
+fn flags() {
+    check_screen("flags", &run_rom("flags", Quirks::default(), &[]));
+}
+
+#[test]
+fn quirks() {
+    for preset in ["default", "chip8", "schip", "xochip"] {
+        let screen = run_rom("quirks", Quirks::preset(preset).unwrap(), &[]);
+        check_screen(&format!("quirks.{}", preset), &screen);
+    }
+}
+
+#[test]
+fn keypad() {
+    let key_events = [(10, 0x5, true), (20, 0x5, false), (30, 0xC, true), (40, 0xC, false)];
+    check_screen("keypad", &run_rom("keypad", Quirks::default(), &key_events));
+}
+
+#[test]
+fn sprites() {
+    check_screen("sprites", &run_rom("sprites", Quirks::default(), &[]));
+}
@@ -0,0 +1,90 @@
+; Flags of the 8XYN instructions. Each case draws the low nibble of the result, then VF :
+;   ADD   FF+01 : 0 1    12+34 : 6 0    FF+FF : E 1
+;   SUB   35-13 : 2 1    13-13 : 0 1    13-35 : E 0
+;   SUBN  35-13 : 2 1    13-13 : 0 1    13-35 : E 0
+;   SHR   81    : 0 1    SHL   81 : 2 1
+;   ADD   FF+01 immediate keeps VF : 0 5
+    LD VB, 0
+    LD VC, 0
+
+    LD V1, 0xFF
+    LD V2, 0x01
+    ADD V1, V2
+    CALL result
+    LD V1, 0x12
+    LD V2, 0x34
+    ADD V1, V2
+    CALL result
+    LD V1, 0xFF
+    LD V2, 0xFF
+    ADD V1, V2
+    CALL result
+    CALL newline
+
+    LD V1, 0x35
+    LD V2, 0x13
+    SUB V1, V2
+    CALL result
+    LD V1, 0x13
+    LD V2, 0x13
+    SUB V1, V2
+    CALL result
+    LD V1, 0x13
+    LD V2, 0x35
+    SUB V1, V2
+    CALL result
+    CALL newline
+
+    LD V1, 0x13
+    LD V2, 0x35
+    SUBN V1, V2
+    CALL result
+    LD V1, 0x13
+    LD V2, 0x13
+    SUBN V1, V2
+    CALL result
+    LD V1, 0x35
+    LD V2, 0x13
+    SUBN V1, V2
+    CALL result
+    CALL newline
+
+    ; VX and VY are equal so that the shift quirk doesn't matter.
+    LD V1, 0x81
+    LD V2, 0x81
+    SHR V1, V2
+    CALL result
+    LD V1, 0x81
+    LD V2, 0x81
+    SHL V1, V2
+    CALL result
+    CALL newline
+
+    LD VF, 0x05
+    LD V1, 0xFF
+    ADD V1, 0x01
+    CALL result
+end:
+    JP end
+
+; Draws V1 and VF, then leaves a gap.
+result:
+    LD V3, VF
+    LD V0, V1
+    CALL digit
+    LD V0, V3
+    CALL digit
+    ADD VB, 0x04
+    RET
+
+newline:
+    LD VB, 0
+    ADD VC, 0x06
+    RET
+
+; Draws the low nibble of V0 at (VB, VC) and moves VB to the next digit.
+digit:
+    LD F, V0
+    DRW VB, VC, 5
+    ADD VB, 0x05
+    RET
@@ -0,0 +1,59 @@
+; Keypad, with key 5 held from frame 10 to 20 and key C from frame 30 to 40. Draws :
+;   5  the key read by LD V0, K
+;   A  the frames it was held, measured with the delay timer
+;   C  the next key read by LD V0, K
+;   1  SKP on the held key C skips
+;   1  SKP on FC skips, only the low nibble selects the key
+;   1  SKNP on the released key 5 skips
+    LD VB, 0
+    LD VC, 0
+
+    LD V0, K
+    CALL digit
+
+    LD V1, V0
+    LD V2, 0x3F
+    LD DT, V2
+held:
+    SKNP V1
+    JP held
+    LD V3, DT
+    SUB V2, V3
+    LD V0, V2
+    CALL digit
+
+    LD V0, K
+    CALL digit
+
+    LD V1, 0x0C
+    LD V0, 0x00
+    SKP V1
+    JP skp_done
+    LD V0, 0x01
+skp_done:
+    CALL digit
+
+    LD V1, 0xFC
+    LD V0, 0x00
+    SKP V1
+    JP mask_done
+    LD V0, 0x01
+mask_done:
+    CALL digit
+
+    LD V1, 0x05
+    LD V0, 0x00
+    SKNP V1
+    JP sknp_done
+    LD V0, 0x01
+sknp_done:
+    CALL digit
+end:
+    JP end
+
+; Draws the low nibble of V0 at (VB, VC) and moves VB to the next digit.
+digit:
+    LD F, V0
+    DRW VB, VC, 5
+    ADD VB, 0x05
+    RET
@@ -0,0 +1,86 @@
+; One digit per quirk, from left to right :
+;   vf_reset      VF after OR                       5 kept, 0 reset
+;   shift         V1 then VF after SHR V1, V2       2 0 in place, 0 1 from VY
+;   load_store    V0 after two LD V0, [I]           1 I kept, 2 I incremented
+;   jump          V1 after JP V0, table             1 NNN + V0, 2 NNN + VX
+;   display_wait  frames taken by four DRW          0 without waiting
+;   vf_order      VF after SUB VF, V1 (1 - 2)       F result, 0 flag
+; clip draws a line at the bottom right corner, which wraps to the left without it.
+    LD VB, 0
+    LD VC, 0
+
+    LD VF, 0x05
+    LD V1, 0x0C
+    LD V2, 0x0A
+    OR V1, V2
+    LD V0, VF
+    CALL digit
+
+    LD V1, 0x04
+    LD V2, 0x81
+    SHR V1, V2
+    LD V3, VF
+    LD V0, V1
+    CALL digit
+    LD V0, V3
+    CALL digit
+
+    LD I, data
+    LD V0, [I]
+    LD V0, [I]
+    CALL digit
+
+    ; The X of BXNN is the high nibble of the table address, VX = 4 skips the JP.
+    LD V0, 0x00
+    LD V1, 0x00
+    LD V2, 0x04
+    LD V3, 0x04
+    JP V0, table
+table:
+    LD V1, 0x01
+    JP jumped
+    LD V1, 0x02
+jumped:
+    LD V0, V1
+    CALL digit
+
+    LD V4, 0x0F
+    LD DT, V4
+    LD I, dot
+    LD V5, 30
+    LD V6, 31
+    DRW V5, V6, 1
+    DRW V5, V6, 1
+    DRW V5, V6, 1
+    DRW V5, V6, 1
+    LD V5, DT
+    SUB V4, V5
+    LD V0, V4
+    CALL digit
+
+    LD VF, 0x01
+    LD V1, 0x02
+    SUB VF, V1
+    LD V0, VF
+    CALL digit
+
+    LD I, line
+    LD V5, 60
+    LD V6, 31
+    DRW V5, V6, 1
+end:
+    JP end
+
+; Draws the low nibble of V0 at (VB, VC) and moves VB to the next digit.
+digit:
+    LD F, V0
+    DRW VB, VC, 5
+    ADD VB, 0x05
+    RET
+
+data:
+    DB 0x01, 0x02
+dot:
+    DB 0x80
+line:
+    DB 0xFF
@@ -0,0 +1,54 @@
+; Sprite drawing. The first two lines hold the 16 font glyphs. Below them :
+;   0 1 0  VF after drawing a box, a square over its corner and a square beside it
+;   the box, with its corner erased by the first square
+;   a box wrapping around the bottom right corner to the top left one
+    LD VB, 8
+    LD VC, 0
+    LD V0, 0x00
+glyphs:
+    CALL digit
+    ADD V0, 0x01
+    SE V0, 0x08
+    JP next_glyph
+    LD VB, 8
+    ADD VC, 0x06
+next_glyph:
+    SE V0, 0x10
+    JP glyphs
+
+    LD VB, 0
+    LD VC, 12
+    LD V4, 30
+    LD V5, 12
+    LD I, box
+    DRW V4, V5, 8
+    LD V0, VF
+    CALL digit
+    LD I, square
+    DRW V4, V5, 4
+    LD V0, VF
+    CALL digit
+    ADD V4, 0x0A
+    DRW V4, V5, 4
+    LD V0, VF
+    CALL digit
+
+    ; Wraps around the corner, the display is not clipped by default.
+    LD I, box
+    LD V4, 60
+    LD V5, 28
+    DRW V4, V5, 8
+end:
+    JP end
+
+; Draws the low nibble of V0 at (VB, VC) and moves VB to the next digit.
+digit:
+    LD F, V0
+    DRW VB, VC, 5
+    ADD VB, 0x05
+    RET
+
+box:
+    DB 0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF
+square:
+    DB 0xF0, 0xF0, 0xF0, 0xF0